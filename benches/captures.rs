@@ -0,0 +1,92 @@
+//! Benchmarks of capture-heavy patterns, which spend much of their time copying saved locations.
+//! Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use regex::ast::{CompileOptions, Regex};
+use regex::program::{Cache, Program, Semantics};
+
+/// Runs `f` repeatedly for about a second, and prints the mean time per run.
+fn bench(name: &str, mut f: impl FnMut()) {
+    // warm up
+    f();
+    let start = Instant::now();
+    let mut runs = 0;
+    while start.elapsed() < Duration::from_secs(1) {
+        f();
+        runs += 1;
+    }
+    let per_run = start.elapsed() / runs;
+    println!("{:<24} {:>12.3?} per run ({} runs)", name, per_run, runs);
+}
+
+fn compile(pattern: &str, semantics: Semantics) -> Program<char> {
+    let options = CompileOptions {
+        semantics,
+        ..Default::default()
+    };
+    Regex::parse(pattern)
+        .unwrap()
+        .compile_with(&options)
+        .unwrap()
+}
+
+fn main() {
+    let log = "2024-05-17 12:34:56 user=alice@example.com action=login ok\n".repeat(200);
+    let words = "the quick brown fox jumps over the lazy dog ".repeat(200);
+
+    let prog = compile(
+        r"([0-9]{4})-([0-9]{2})-([0-9]{2}) ([0-9]{2}):([0-9]{2}):([0-9]{2}) user=([a-z]+)@([a-z]+)\.([a-z]+) action=([a-z]+)",
+        Semantics::LeftmostFirst,
+    );
+    bench("log lines", || {
+        black_box(prog.captures_iter(log.as_str()).count());
+    });
+
+    // a single search through a long input, with many threads in flight at each position
+    let haystack = "1234-56-78 12:34:56 user=bob@".repeat(200) + &log[..60];
+    bench("late match", || {
+        black_box(prog.captures(haystack.as_str()));
+    });
+
+    let prog = compile(
+        r"(?:(the)|(quick)|(brown)|(fox)|(jumps)|(over)|(lazy)|(dog))+",
+        Semantics::LeftmostFirst,
+    );
+    bench("alternation", || {
+        black_box(prog.captures_iter(words.as_str()).count());
+    });
+
+    let prog = compile(
+        r"(?:(the)|(quick)|(brown)|(fox)|(jumps)|(over)|(lazy)|(dog)| )+",
+        Semantics::LeftmostLongest,
+    );
+    bench("alternation (longest)", || {
+        black_box(prog.captures(words.as_str()));
+    });
+
+    // many short inputs, with and without reusing a cache
+    let lines = log.lines().collect::<Vec<_>>();
+    let prog = compile(
+        r"user=([a-z]+)@([a-z]+)\.([a-z]+)",
+        Semantics::LeftmostFirst,
+    );
+    bench("short inputs", || {
+        for line in &lines {
+            black_box(prog.exec(*line));
+        }
+    });
+    let mut cache = Cache::new(&prog);
+    bench("short inputs (cached)", || {
+        for line in &lines {
+            black_box(prog.exec_with(&mut cache, *line));
+        }
+    });
+
+    let prog = compile(r"(a|ab)(c|bcd)?(d*)([a-z])", Semantics::AllPaths);
+    let haystack = "abcdddx ".repeat(50);
+    bench("all parses", || {
+        black_box(prog.all_parses(haystack.as_str(), None).len());
+    });
+}
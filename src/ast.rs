@@ -1,10 +1,15 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::ops;
 
+use crate::class::{Class, Ranges};
+use crate::parse::{ParseError, Parser};
 use crate::program;
 use crate::token::Token;
 
 /// A regular expression
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Regex<T: Token> {
     /// Matches an empty string, i.e. zero tokens.
     Empty,
@@ -12,121 +17,292 @@ pub enum Regex<T: Token> {
     Literal(Vec<T>),
     /// Matches any single token.
     Any,
+    /// Matches any single token except a line terminator, unless the `s` flag is set. This is what
+    /// `.` parses to.
+    Dot,
     /// Matches a word boundary.
     WordBoundary,
+    /// Matches anywhere except at a word boundary.
+    NotWordBoundary,
+    /// Matches the start of the input.
+    StartOfInput,
+    /// Matches the end of the input.
+    EndOfInput,
+    /// Matches the start of a line, i.e. the start of the input or a position following a line
+    /// terminator.
+    StartOfLine,
+    /// Matches the end of a line, i.e. the end of the input or a position preceding a line
+    /// terminator.
+    EndOfLine,
     /// Matches a single token from a set of tokens.
     Set(HashSet<T>),
+    /// Matches a single token not in a set of tokens.
+    NotSet(HashSet<T>),
+    /// Matches a single token in (or, if negated, not in) a set of ranges of tokens.
+    Ranges(Ranges<T>),
+    /// Matches a single token in a user-defined class.
+    Class(Class<T>),
     /// Matches a repeating pattern
     Repeat(Box<Regex<T>>, Repeater),
-    /// Generates a capturing group
-    Capture(Box<Regex<T>>),
+    /// Generates a capturing group, which may be given a name. Groups are numbered in the order of
+    /// their opening parentheses, starting from 1, whether or not they have names.
+    Capture(Box<Regex<T>>, Option<String>),
     /// Matches a concatenation of two patterns
     Concat(Vec<Regex<T>>),
     /// Matches one of multiple patterns
     Alternate(Vec<Regex<T>>),
+    /// Matches a pattern with some flags set or cleared.
+    Flags(Flags, Box<Regex<T>>),
 }
 
 impl<T: Token> Regex<T> {
+    /// Compiles a regular expression into a program to be executed, using the default
+    /// `CompileOptions`.
+    pub fn compile(self) -> Result<program::Program<T>, CompileError> {
+        self.compile_with(&CompileOptions::default())
+    }
+
     /// Compiles a regular expression into a program to be executed.
-    pub fn compile(self) -> program::Program<T> {
+    pub fn compile_with(
+        self,
+        options: &CompileOptions,
+    ) -> Result<program::Program<T>, CompileError> {
         use crate::program::Instr::*;
-        // first, match /.*?/ to find earliest start of match, and save match point
-        let mut v = vec![JSplit(3), Any, Jump(0), Save(0)];
-        let mut num_captures = 0;
-        self.compile_partial(&mut v, &mut num_captures);
+        let mut c = Compiler {
+            prog: Vec::new(),
+            num_captures: 0,
+            names: HashMap::new(),
+            dot_matches_line_terminator: options.dot_matches_line_terminator,
+            case_insensitive: options.case_insensitive,
+            options,
+        };
+        if !options.anchored {
+            // first, match /.*?/ to find earliest start of match
+            for instr in [JSplit(3), Any, Jump(0)] {
+                c.push(instr)?;
+            }
+        }
+        // save match point
+        c.push(Save(0))?;
+        self.compile_partial(&mut c)?;
         // save end of match
-        v.push(Save(1));
+        c.push(Save(1))?;
         // finish
-        v.push(Match);
+        c.push(Match)?;
 
         // construct final program
-        program::Program::new(v, 2 + num_captures * 2)
+        Ok(program::Program::new(c.prog, 2 + c.num_captures * 2)
+            .with_semantics(options.semantics)
+            .with_names(c.names))
     }
 
-    fn compile_partial(self, v: &mut Vec<program::Instr<T>>, num_captures: &mut usize) {
+    fn compile_partial(self, c: &mut Compiler<T>) -> Result<(), CompileError> {
         use crate::program::Instr::*;
         match self {
             Regex::Empty => {}
             Regex::Literal(toks) => {
                 for t in toks {
-                    v.push(Token(t));
+                    c.push_folded(Token(t))?;
                 }
             }
             Regex::Any => {
-                v.push(Any);
+                c.push(Any)?;
+            }
+            Regex::Dot => {
+                if c.dot_matches_line_terminator {
+                    c.push(Any)?;
+                } else {
+                    c.push(NotLineTerminator)?;
+                }
             }
             Regex::WordBoundary => {
-                v.push(WordBoundary);
+                c.push(WordBoundary)?;
+            }
+            Regex::NotWordBoundary => {
+                c.push(NotWordBoundary)?;
+            }
+            Regex::StartOfInput => {
+                c.push(StartOfInput)?;
+            }
+            Regex::EndOfInput => {
+                c.push(EndOfInput)?;
+            }
+            Regex::StartOfLine => {
+                c.push(StartOfLine)?;
+            }
+            Regex::EndOfLine => {
+                c.push(EndOfLine)?;
             }
             Regex::Set(set) => {
-                v.push(Set(set));
+                c.push_folded(Set(set))?;
+            }
+            Regex::NotSet(set) => {
+                c.push_folded(NotSet(set))?;
+            }
+            Regex::Ranges(ranges) => {
+                c.push_folded(Ranges(ranges))?;
+            }
+            Regex::Class(class) => {
+                c.push(Class(class))?;
             }
             Regex::Repeat(e, rep) => {
                 use self::Repeater::*;
                 match rep {
                     ZeroOrOne(greedy) => {
                         // store current length of vector, i.e. index of `Split` instruction
-                        let i = v.len();
+                        let i = c.prog.len();
                         // dummy instruction
-                        v.push(Split(0));
+                        c.push(Split(0))?;
                         // compile `e`
-                        e.compile_partial(v, num_captures);
+                        e.compile_partial(c)?;
                         if greedy {
                             // prefer not to skip to end
-                            v[i] = Split(v.len());
+                            c.prog[i] = Split(c.prog.len());
                         } else {
                             // prefer to skip to end
-                            v[i] = JSplit(v.len());
+                            c.prog[i] = JSplit(c.prog.len());
                         }
                     }
                     ZeroOrMore(greedy) => {
                         // save current pc
-                        let start = v.len();
+                        let start = c.prog.len();
                         // dummy instruction
-                        v.push(Split(0));
+                        c.push(Split(0))?;
                         // compile `e`
-                        e.compile_partial(v, num_captures);
+                        e.compile_partial(c)?;
                         // repeat
-                        v.push(Jump(start));
+                        c.push(Jump(start))?;
                         if greedy {
                             // prefer not to skip to end
-                            v[start] = Split(v.len());
+                            c.prog[start] = Split(c.prog.len());
                         } else {
                             // prefer to skip to end
-                            v[start] = JSplit(v.len());
+                            c.prog[start] = JSplit(c.prog.len());
                         }
                     }
                     OneOrMore(greedy) => {
                         // save current pc
-                        let start = v.len();
+                        let start = c.prog.len();
                         // compile `e`
                         // here we're just putting it directly on the stack
-                        e.compile_partial(v, num_captures);
+                        e.compile_partial(c)?;
                         if greedy {
                             // prefer to repeat
-                            v.push(JSplit(start));
+                            c.push(JSplit(start))?;
                         } else {
                             // prefer not to repeat, i.e. prefer to continue
-                            v.push(Split(start));
+                            c.push(Split(start))?;
+                        }
+                    }
+                    Range {
+                        min,
+                        max: None,
+                        greedy,
+                    } => {
+                        if min == 0 {
+                            // same as `*`
+                            return Regex::Repeat(e, ZeroOrMore(greedy)).compile_partial(c);
+                        }
+                        // `e{n,}` is the same as `e{n-1}e+`, so compile `e` once, copy it until
+                        // there are `n` copies, and then allow the last copy to repeat
+                        let start = c.prog.len();
+                        e.compile_partial(c)?;
+                        let end = c.prog.len();
+                        if start == end {
+                            // `e` compiled to nothing, so it only matches the empty string, and
+                            // repeating it would only waste time copying nothing `min` times
+                            return Ok(());
+                        }
+                        let mut last = start;
+                        for _ in 1..min {
+                            last = c.prog.len();
+                            c.copy(start..end)?;
+                        }
+                        if greedy {
+                            // prefer to repeat
+                            c.push(JSplit(last))?;
+                        } else {
+                            // prefer not to repeat, i.e. prefer to continue
+                            c.push(Split(last))?;
+                        }
+                    }
+                    Range {
+                        min,
+                        max: Some(max),
+                        greedy,
+                    } => {
+                        if min > max {
+                            return Err(CompileError::InvalidRange { min, max });
+                        }
+                        if max == 0 {
+                            // `e` is never matched, but it still needs to be compiled so that any
+                            // capturing groups inside it are numbered consistently
+                            let start = c.prog.len();
+                            e.compile_partial(c)?;
+                            c.prog.truncate(start);
+                            return Ok(());
+                        }
+                        // compile `e` once, and then copy it until there are `max` copies. Each
+                        // copy after the first `min` is preceded by a split which can skip to the
+                        // end of all the copies. Copying the compiled program rather than
+                        // compiling `e` again means that any capturing groups inside it keep the
+                        // same numbers in every copy.
+                        let mut splits = Vec::new();
+                        if min == 0 {
+                            splits.push(c.prog.len());
+                            // dummy instruction
+                            c.push(Split(0))?;
+                        }
+                        let start = c.prog.len();
+                        e.compile_partial(c)?;
+                        let end = c.prog.len();
+                        // if `e` compiled to nothing, it only matches the empty string, so a
+                        // single copy will do, rather than copying nothing `max` times
+                        let max = if start == end { 1 } else { max };
+                        for i in 1..max {
+                            if i >= min {
+                                splits.push(c.prog.len());
+                                // dummy instruction
+                                c.push(Split(0))?;
+                            }
+                            c.copy(start..end)?;
+                        }
+                        // now, set splits correctly -- all splits should point to where we are
+                        // now.
+                        let pc = c.prog.len();
+                        for i in splits {
+                            if greedy {
+                                // prefer not to skip to end
+                                c.prog[i] = Split(pc);
+                            } else {
+                                // prefer to skip to end
+                                c.prog[i] = JSplit(pc);
+                            }
                         }
                     }
                 }
             }
-            Regex::Capture(e) => {
+            Regex::Capture(e, name) => {
                 // increment
-                *num_captures += 1;
+                c.num_captures += 1;
                 // save current value of `num_captures`, incase `e` has any captures
-                let n = *num_captures;
+                let n = c.num_captures;
+                if let Some(name) = name {
+                    if c.names.contains_key(&name) {
+                        return Err(CompileError::DuplicateGroupName(name));
+                    }
+                    c.names.insert(name, n);
+                }
                 // save begining of capture
-                v.push(Save(n * 2));
+                c.push(Save(n * 2))?;
                 // match `e`
-                e.compile_partial(v, num_captures);
+                e.compile_partial(c)?;
                 // save end of capture
-                v.push(Save(n * 2 + 1));
+                c.push(Save(n * 2 + 1))?;
             }
             Regex::Concat(es) => {
                 for e in es {
-                    e.compile_partial(v, num_captures);
+                    e.compile_partial(c)?;
                 }
             }
             Regex::Alternate(mut es) => {
@@ -144,42 +320,231 @@ impl<T: Token> Regex<T> {
 
                     for e in es {
                         // save split location
-                        splits.push(v.len());
+                        splits.push(c.prog.len());
                         // dummy instruction
-                        v.push(Split(0));
+                        c.push(Split(0))?;
                         // compile `e`
-                        e.compile_partial(v, num_captures);
+                        e.compile_partial(c)?;
                         // store jump location
-                        jumps.push(v.len());
+                        jumps.push(c.prog.len());
                         // dummy instruction
-                        v.push(Jump(0));
+                        c.push(Jump(0))?;
                     }
 
                     // now, set splits correctly -- each split should point to the next one, except
                     // for the last, which should point where we are now.
-                    splits.push(v.len());
+                    splits.push(c.prog.len());
                     for (i, j) in splits.iter().zip(splits[1..].iter()) {
-                        v[*i] = Split(*j);
+                        c.prog[*i] = Split(*j);
                     }
 
                     // compile `last`
-                    last.compile_partial(v, num_captures);
+                    last.compile_partial(c)?;
 
                     // now, set jumps correctly -- all jumps should point to where we are now.
-                    let pc = v.len();
+                    let pc = c.prog.len();
                     for i in jumps {
-                        v[i] = Jump(pc);
+                        c.prog[i] = Jump(pc);
                     }
                 }
                 // if the `if let` failed, it means that `es` was empty, so we should do nothing
             }
+            Regex::Flags(flags, e) => {
+                // the flags only apply within `e`
+                let (dot_matches_line_terminator, case_insensitive) =
+                    (c.dot_matches_line_terminator, c.case_insensitive);
+                if let Some(dot) = flags.dot_matches_line_terminator {
+                    c.dot_matches_line_terminator = dot;
+                }
+                if let Some(fold) = flags.case_insensitive {
+                    c.case_insensitive = fold;
+                }
+                e.compile_partial(c)?;
+                c.dot_matches_line_terminator = dot_matches_line_terminator;
+                c.case_insensitive = case_insensitive;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state of a compilation in progress.
+struct Compiler<'o, T: Token> {
+    /// The instructions compiled so far.
+    prog: Vec<program::Instr<T>>,
+    /// The number of capturing groups compiled so far.
+    num_captures: usize,
+    /// The group numbers of the named capturing groups compiled so far.
+    names: HashMap<String, usize>,
+    /// Whether `Regex::Dot` matches line terminators, as set by the innermost enclosing `Flags`.
+    dot_matches_line_terminator: bool,
+    /// Whether tokens are compared after case folding, as set by the innermost enclosing `Flags`.
+    case_insensitive: bool,
+    options: &'o CompileOptions,
+}
+
+impl<'o, T: Token> Compiler<'o, T> {
+    /// Checks that `n` more instructions can be added without exceeding the size limit.
+    fn reserve(&self, n: usize) -> Result<(), CompileError> {
+        match self.options.size_limit {
+            Some(limit) if self.prog.len() + n > limit => {
+                Err(CompileError::SizeLimitExceeded(limit))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds an instruction to the end of the program.
+    fn push(&mut self, instr: program::Instr<T>) -> Result<(), CompileError> {
+        self.reserve(1)?;
+        self.prog.push(instr);
+        Ok(())
+    }
+
+    /// Adds a `Token`, `Set`, `NotSet` or `Ranges` instruction to the end of the program, wrapping
+    /// it in `Fold` if case-insensitive mode is on. The tokens of the others are folded, but
+    /// ranges are kept as they are, since folding their bounds would change which tokens fall
+    /// between them.
+    fn push_folded(&mut self, instr: program::Instr<T>) -> Result<(), CompileError> {
+        use crate::program::Instr::*;
+        if !self.case_insensitive {
+            return self.push(instr);
+        }
+        let folded = match instr {
+            Token(t) => Token(t.fold()),
+            Set(set) => Set(set.iter().map(T::fold).collect()),
+            NotSet(set) => NotSet(set.iter().map(T::fold).collect()),
+            instr => instr,
+        };
+        self.push(Fold(Box::new(folded)))
+    }
+
+    /// Adds a copy of the instructions in `range` to the end of the program. The instructions in
+    /// `range` must have been compiled from a single sub-expression, so that they only refer to
+    /// locations within the range, or to the location immediately after it.
+    fn copy(&mut self, range: ops::Range<program::InstrPtr>) -> Result<(), CompileError> {
+        self.reserve(range.len())?;
+        let offset = self.prog.len() - range.start;
+        for pc in range {
+            let instr = self.prog[pc].relocate(offset);
+            self.prog.push(instr);
+        }
+        Ok(())
+    }
+}
+
+/// Options controlling how a `Regex` is compiled.
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    /// The maximum number of instructions in the compiled program, or `None` for no limit.
+    /// Bounded repetitions copy the repeated sub-program, so a small pattern like `(a{1000}){1000}`
+    /// can produce a very large program.
+    pub size_limit: Option<usize>,
+    /// Whether matches must start at the beginning of the input. By default, the program starts
+    /// with `.*?`, so that it finds matches starting anywhere in the input; an anchored program
+    /// leaves this out, and stops as soon as no match is possible. Combined with
+    /// `Regex::EndOfInput`, this matches the input as a whole.
+    pub anchored: bool,
+    /// The match semantics of the compiled program. By default, `LeftmostFirst`.
+    pub semantics: program::Semantics,
+    /// Whether `Regex::Dot` matches line terminators outside of any `Flags` which set or clear the
+    /// `s` flag.
+    pub dot_matches_line_terminator: bool,
+    /// Whether tokens are compared after case folding, as given by `Token::fold`, outside of any
+    /// `Flags` which set or clear the `i` flag. This applies to literals, sets and ranges, but not
+    /// to user-defined classes.
+    pub case_insensitive: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> CompileOptions {
+        CompileOptions {
+            size_limit: Some(1 << 20),
+            anchored: false,
+            semantics: program::Semantics::LeftmostFirst,
+            dot_matches_line_terminator: false,
+            case_insensitive: false,
+        }
+    }
+}
+
+/// An error encountered while compiling a `Regex`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompileError {
+    /// The compiled program would have more instructions than the size limit, given here.
+    SizeLimitExceeded(usize),
+    /// A bounded repetition whose minimum count is greater than its maximum.
+    InvalidRange { min: usize, max: usize },
+    /// Two capturing groups with the same name.
+    DuplicateGroupName(String),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::SizeLimitExceeded(limit) => write!(
+                f,
+                "compiled program exceeds the size limit of {} instructions",
+                limit
+            ),
+            CompileError::InvalidRange { min, max } => write!(
+                f,
+                "repetition minimum {} is greater than maximum {}",
+                min, max
+            ),
+            CompileError::DuplicateGroupName(name) => {
+                write!(f, "duplicate capturing group name {:?}", name)
+            }
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+impl Regex<char> {
+    /// Parses a regular expression from its textual syntax.
+    pub fn parse(pattern: &str) -> Result<Regex<char>, ParseError> {
+        Parser::new(pattern).parse()
+    }
+}
+
+/// Flags which change how part of a pattern is parsed or matched, written as `(?flags)` to apply
+/// to the rest of the enclosing group, or `(?flags:...)` to apply to a group of its own. Each flag
+/// can be set, cleared, or left as it is in the enclosing group, which is written as e.g. `(?i-s)`
+/// to set `i` and clear `s`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Flags {
+    /// `i`: literals, sets and ranges match tokens regardless of case, by comparing them after
+    /// `Token::fold`. User-defined classes are unaffected.
+    pub case_insensitive: Option<bool>,
+    /// `m`: `^` and `$` match at the start and end of each line, and parse to
+    /// `Regex::StartOfLine` and `Regex::EndOfLine` rather than the input anchors. This only
+    /// affects parsing and printing.
+    pub multi_line: Option<bool>,
+    /// `s`: `Regex::Dot` also matches line terminators.
+    pub dot_matches_line_terminator: Option<bool>,
+    /// `x`: whitespace and comments from `#` to the end of the line are ignored in the pattern,
+    /// except inside sets. This only affects parsing and printing.
+    pub verbose: Option<bool>,
+}
+
+impl Flags {
+    /// Returns the flags which result from applying `later` after `self`.
+    pub fn then(self, later: Flags) -> Flags {
+        Flags {
+            case_insensitive: later.case_insensitive.or(self.case_insensitive),
+            multi_line: later.multi_line.or(self.multi_line),
+            dot_matches_line_terminator: later
+                .dot_matches_line_terminator
+                .or(self.dot_matches_line_terminator),
+            verbose: later.verbose.or(self.verbose),
         }
     }
 }
 
-/// The type of a repetition. In each enum variant, the one argument determines whether or not the
-/// repetition should be greedy, i.e. preferring to match longer strings over shorter strings.
-#[derive(Debug)]
+/// The type of a repetition. In each enum variant, the `bool` argument determines whether or not
+/// the repetition should be greedy, i.e. preferring to match longer strings over shorter strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Repeater {
     /// Matches zero or one instances, i.e. `?` or `??`
     ZeroOrOne(bool),
@@ -187,4 +552,11 @@ pub enum Repeater {
     ZeroOrMore(bool),
     /// Matches one or more instances, i.e. `+` or `+?`
     OneOrMore(bool),
+    /// Matches between `min` and `max` instances, or at least `min` instances if `max` is `None`,
+    /// i.e. `{min,max}`, `{min,}`, or `{min}`, or their lazy forms with a trailing `?`
+    Range {
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
 }
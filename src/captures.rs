@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::ops::{Index, Range};
+use std::slice;
+use std::sync::Arc;
+
+use crate::program::SaveList;
+
+/// The span of a match, or of a capturing group within one, in the index convention of the
+/// searcher which produced it: byte offsets for a `&str`, and token positions for a slice or an
+/// iterator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Match {
+    start: usize,
+    end: usize,
+}
+
+impl Match {
+    /// Creates a match spanning `start..end`.
+    pub fn new(start: usize, end: usize) -> Match {
+        Match { start, end }
+    }
+
+    /// The index of the start of the match.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The index just past the end of the match.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// The span of the match, as a range.
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// The length of the match, in the units of the searcher's indices.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Whether the match is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the part of `haystack` covered by the match. The haystack must be indexed the same
+    /// way as the input which was searched, e.g. a `str` searched as a `&str`, or a slice searched
+    /// as a `&[T]`.
+    pub fn slice<'h, H>(&self, haystack: &'h H) -> &'h H::Output
+    where
+        H: Index<Range<usize>> + ?Sized,
+    {
+        &haystack[self.range()]
+    }
+}
+
+impl From<Range<usize>> for Match {
+    fn from(range: Range<usize>) -> Match {
+        Match::new(range.start, range.end)
+    }
+}
+
+impl From<Match> for Range<usize> {
+    fn from(m: Match) -> Range<usize> {
+        m.range()
+    }
+}
+
+/// The positions of the capturing groups of a single match. Group 0 is the match as a whole, and
+/// group _n_ is the span between save slots _2n_ and _2n + 1_. Named groups can also be looked up
+/// by name.
+///
+/// Captures compare equal to a `SaveList` with the same slots, so they can be checked against the
+/// raw output of a `Program`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Captures {
+    slots: SaveList,
+    /// The group numbers of the named groups of the program which found the match.
+    names: Arc<HashMap<String, usize>>,
+}
+
+impl Captures {
+    /// Wraps the saved locations of a thread which reached `Match`, along with the names of the
+    /// program's capturing groups.
+    pub(crate) fn new(slots: SaveList, names: Arc<HashMap<String, usize>>) -> Captures {
+        Captures { slots, names }
+    }
+
+    /// The span of the given group, or `None` if the group didn't participate in the match.
+    pub fn get(&self, group: usize) -> Option<Range<usize>> {
+        match (self.slots.get(group * 2)?, self.slots.get(group * 2 + 1)?) {
+            (Some(start), Some(end)) => Some(*start..*end),
+            _ => None,
+        }
+    }
+
+    /// The span of the group with the given name, or `None` if there is no such group, or it
+    /// didn't participate in the match.
+    pub fn name(&self, name: &str) -> Option<Range<usize>> {
+        self.get(*self.names.get(name)?)
+    }
+
+    /// The span of the whole match, i.e. group 0, or `None` if the program doesn't save it.
+    pub fn get_match(&self) -> Option<Match> {
+        self.get(0).map(Match::from)
+    }
+
+    /// Returns the part of `haystack` covered by the given group, or `None` if the group didn't
+    /// participate in the match. See `Match::slice`.
+    pub fn slice<'h, H>(&self, haystack: &'h H, group: usize) -> Option<&'h H::Output>
+    where
+        H: Index<Range<usize>> + ?Sized,
+    {
+        self.get(group).map(|range| &haystack[range])
+    }
+
+    /// The number of groups, including group 0.
+    pub fn len(&self) -> usize {
+        self.slots.len().div_ceil(2)
+    }
+
+    /// Whether there are no groups at all, which is only the case for a program without any save
+    /// slots.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Iterates over the spans of every group, in order, starting with group 0.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            slots: self.slots.chunks(2),
+        }
+    }
+
+    /// The raw saved locations.
+    pub fn slots(&self) -> &[Option<usize>] {
+        &self.slots
+    }
+
+    /// Returns the raw saved locations.
+    pub fn into_slots(self) -> SaveList {
+        self.slots
+    }
+}
+
+impl PartialEq<SaveList> for Captures {
+    fn eq(&self, other: &SaveList) -> bool {
+        self.slots == *other
+    }
+}
+
+impl<'c> IntoIterator for &'c Captures {
+    type Item = Option<Range<usize>>;
+    type IntoIter = Iter<'c>;
+
+    fn into_iter(self) -> Iter<'c> {
+        self.iter()
+    }
+}
+
+/// An iterator over the spans of the groups of a `Captures`. Created by `Captures::iter`.
+#[derive(Clone, Debug)]
+pub struct Iter<'c> {
+    slots: slice::Chunks<'c, Option<usize>>,
+}
+
+impl<'c> Iterator for Iter<'c> {
+    type Item = Option<Range<usize>>;
+
+    fn next(&mut self) -> Option<Option<Range<usize>>> {
+        match self.slots.next()? {
+            [Some(start), Some(end)] => Some(Some(*start..*end)),
+            _ => Some(None),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.slots.size_hint()
+    }
+}
+
+impl<'c> ExactSizeIterator for Iter<'c> {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Captures, Match};
+
+    #[test]
+    fn captures() {
+        let names = [("first".to_string(), 1), ("second".to_string(), 2)];
+        let caps = Captures::new(
+            vec![Some(1), Some(4), Some(1), Some(2), None, None],
+            Arc::new(names.iter().cloned().collect()),
+        );
+        assert_eq!(caps.len(), 3);
+        assert_eq!(caps.get(0), Some(1..4));
+        assert_eq!(caps.get(2), None);
+        assert_eq!(caps.get(3), None);
+        assert_eq!(caps.name("first"), Some(1..2));
+        assert_eq!(caps.name("second"), None);
+        assert_eq!(caps.name("third"), None);
+        assert_eq!(caps.get_match(), Some(Match::new(1, 4)));
+        assert_eq!(
+            caps.iter().collect::<Vec<_>>(),
+            vec![Some(1..4), Some(1..2), None]
+        );
+        let haystack = "xabcx";
+        assert_eq!(caps.slice(haystack, 0), Some("abc"));
+        assert_eq!(caps.slice(haystack, 1), Some("a"));
+        assert_eq!(caps.slice(haystack, 2), None);
+        let haystack = ['x', 'a', 'b', 'c', 'x'];
+        assert_eq!(caps.slice(&haystack[..], 0), Some(&['a', 'b', 'c'][..]));
+        assert_eq!(
+            caps.get_match().unwrap().slice(&haystack[..]),
+            &['a', 'b', 'c']
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+/// A class of tokens, matching any token which falls within one of a list of inclusive ranges, or,
+/// if the class is negated, any token which doesn't.
+///
+/// Constructing a `Ranges` requires the tokens to be `Ord`, but matching against it doesn't, so it
+/// can be used in a `Program` over any `Token` type. The comparison function is captured when the
+/// class is created.
+#[derive(Clone)]
+pub struct Ranges<T> {
+    ranges: Vec<(T, T)>,
+    negated: bool,
+    cmp: fn(&T, &T) -> Ordering,
+}
+
+impl<T: Ord> Ranges<T> {
+    /// Creates a class matching any token `t` with `lo <= t <= hi` for one of the given `(lo, hi)`
+    /// pairs. A single token can be included with the pair `(t, t)`.
+    pub fn new(ranges: impl IntoIterator<Item = (T, T)>) -> Ranges<T> {
+        Ranges {
+            ranges: ranges.into_iter().collect(),
+            negated: false,
+            cmp: Ord::cmp,
+        }
+    }
+}
+
+impl<T> Ranges<T> {
+    /// Returns the complement of this class.
+    pub fn negate(self) -> Ranges<T> {
+        Ranges {
+            negated: !self.negated,
+            ..self
+        }
+    }
+
+    /// The `(lo, hi)` pairs of this class, in the order they were given.
+    pub fn ranges(&self) -> &[(T, T)] {
+        &self.ranges
+    }
+
+    /// Whether this class matches tokens outside of its ranges rather than inside them.
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Returns whether `tok` is a member of this class.
+    pub fn contains(&self, tok: &T) -> bool {
+        self.in_ranges(tok) != self.negated
+    }
+
+    /// Returns whether any of `toks` falls within one of the ranges, or, if the class is negated,
+    /// whether none of them do. Used to match the different case forms of a token in
+    /// case-insensitive mode.
+    pub(crate) fn contains_any(&self, toks: &[&T]) -> bool {
+        toks.iter().any(|tok| self.in_ranges(tok)) != self.negated
+    }
+
+    /// Returns whether `tok` falls within one of the ranges, ignoring negation.
+    fn in_ranges(&self, tok: &T) -> bool {
+        self.ranges.iter().any(|(lo, hi)| {
+            (self.cmp)(lo, tok) != Ordering::Greater && (self.cmp)(tok, hi) != Ordering::Greater
+        })
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Ranges<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Ranges")
+            .field("ranges", &self.ranges)
+            .field("negated", &self.negated)
+            .finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for Ranges<T> {
+    fn eq(&self, other: &Ranges<T>) -> bool {
+        self.ranges == other.ranges && self.negated == other.negated
+    }
+}
+
+/// A predicate deciding whether a token belongs to a class. Implemented for all suitable
+/// closures, so a class can be defined as e.g. `|w: &Word| w.pos == Pos::Noun`.
+pub trait TokenClass<T>: Send + Sync {
+    /// Returns whether `tok` is a member of the class.
+    fn matches(&self, tok: &T) -> bool;
+}
+
+impl<T, F> TokenClass<T> for F
+where
+    F: Fn(&T) -> bool + Send + Sync,
+{
+    fn matches(&self, tok: &T) -> bool {
+        self(tok)
+    }
+}
+
+/// A named class of tokens, defined by an arbitrary `TokenClass`.
+///
+/// Since the predicate itself can't be inspected, classes are identified by their name: two
+/// `Class`es with the same name compare equal, and the name is all that is shown by `Debug`.
+/// Cloning a `Class` shares the underlying predicate.
+#[derive(Clone)]
+pub struct Class<T> {
+    name: Arc<str>,
+    class: Arc<dyn TokenClass<T>>,
+}
+
+impl<T> Class<T> {
+    /// Creates a new class with the given name and predicate.
+    pub fn new(name: &str, class: impl TokenClass<T> + 'static) -> Class<T> {
+        Class {
+            name: name.into(),
+            class: Arc::new(class),
+        }
+    }
+
+    /// The name of this class.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns whether `tok` is a member of this class.
+    pub fn contains(&self, tok: &T) -> bool {
+        self.class.matches(tok)
+    }
+}
+
+impl<T> fmt::Debug for Class<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Class").field(&self.name).finish()
+    }
+}
+
+impl<T> PartialEq for Class<T> {
+    fn eq(&self, other: &Class<T>) -> bool {
+        self.name == other.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Class, Ranges};
+
+    #[test]
+    fn contains() {
+        let class = Ranges::new(vec![('a', 'z'), ('0', '9'), ('_', '_')]);
+        assert!(class.contains(&'a'));
+        assert!(class.contains(&'q'));
+        assert!(class.contains(&'z'));
+        assert!(class.contains(&'5'));
+        assert!(class.contains(&'_'));
+        assert!(!class.contains(&'A'));
+        assert!(!class.contains(&'-'));
+        let class = class.negate();
+        assert!(!class.contains(&'q'));
+        assert!(class.contains(&'A'));
+    }
+
+    #[test]
+    fn class() {
+        let vowel = Class::new("vowel", |c: &char| "aeiou".contains(*c));
+        assert!(vowel.contains(&'e'));
+        assert!(!vowel.contains(&'x'));
+        assert_eq!(format!("{:?}", vowel), "Class(\"vowel\")");
+        // classes are identified by name
+        assert_eq!(vowel, Class::new("vowel", |_: &char| false));
+        assert_ne!(vowel, Class::new("consonant", |_: &char| false));
+    }
+}
@@ -0,0 +1,357 @@
+use std::borrow::Borrow;
+
+use crate::captures::{Captures, Match};
+use crate::program::{Cache, Program, SaveList, Semantics};
+use crate::searcher::{IntoSearcher, Replay, Searcher};
+use crate::token::Token;
+
+impl<T: Token> Program<T> {
+    /// Returns whether the program matches anywhere in the input. This doesn't keep track of any
+    /// saved locations, and stops reading the input as soon as a match is found, or once no match
+    /// is possible.
+    pub fn is_match<U: Borrow<T>>(&self, input: impl IntoSearcher<U>) -> bool {
+        self.exec_is_match(input.into_searcher())
+    }
+
+    /// Finds the leftmost match in the input. See `captures`.
+    pub fn find<U: Borrow<T>>(&self, input: impl IntoSearcher<U>) -> Option<Match> {
+        self.captures(input).as_ref().and_then(Captures::get_match)
+    }
+
+    /// Finds the leftmost match in the input, along with the positions of its capturing groups.
+    ///
+    /// The match is chosen according to the program's `Semantics`, except that `AllPaths`
+    /// programs are run with `LeftmostFirst` semantics, since only one match is reported. The
+    /// input is only read as far as is needed to decide on the match.
+    pub fn captures<U: Borrow<T>>(&self, input: impl IntoSearcher<U>) -> Option<Captures> {
+        self.exec_searcher(
+            &mut Cache::new(self),
+            input.into_searcher(),
+            0,
+            None,
+            self.search_semantics(),
+            None,
+        )
+        .pop()
+        .map(|saved| self.captures_from(saved))
+    }
+
+    /// Returns an iterator over the successive non-overlapping matches in the input. See
+    /// `captures_iter`.
+    pub fn find_iter<I, U>(&self, input: I) -> FindIter<'_, T, I::Searcher>
+    where
+        I: IntoSearcher<U>,
+        U: Borrow<T> + Clone,
+    {
+        FindIter(self.captures_iter(input))
+    }
+
+    /// Returns an iterator over the successive non-overlapping matches in the input, along with
+    /// the positions of their capturing groups.
+    ///
+    /// Each match is found as by `captures`, starting from the end of the previous match, so the
+    /// matches are found lazily, and the input is only read as far as is needed for the matches
+    /// which have been taken from the iterator. The iterator keeps a `Cache` for all of its
+    /// searches, and only buffers the tokens which a later search could need. An empty match
+    /// which starts where the previous match ended is skipped, and each empty match is followed
+    /// by a search starting one token later, so that iteration always makes progress.
+    ///
+    /// The program must save the span of the whole match in slots 0 and 1, as compiled programs
+    /// do; otherwise, iteration stops at the first match.
+    pub fn captures_iter<I, U>(&self, input: I) -> CapturesIter<'_, T, I::Searcher>
+    where
+        I: IntoSearcher<U>,
+        U: Borrow<T> + Clone,
+    {
+        CapturesIter {
+            prog: self,
+            cache: Cache::new(self),
+            input: Replay::new(input.into_searcher()),
+            last_end: None,
+            done: false,
+        }
+    }
+
+    /// The semantics used to find a single match.
+    fn search_semantics(&self) -> Semantics {
+        match self.semantics() {
+            Semantics::AllPaths => Semantics::LeftmostFirst,
+            semantics => semantics,
+        }
+    }
+}
+
+/// An iterator over the successive non-overlapping matches of a program, along with the positions
+/// of their capturing groups. Created by `Program::captures_iter`.
+pub struct CapturesIter<'p, T: Token, S: Searcher> {
+    prog: &'p Program<T>,
+    cache: Cache,
+    input: Replay<S>,
+    /// The end of the previous match, if any.
+    last_end: Option<usize>,
+    /// Whether there can't be any more matches.
+    done: bool,
+}
+
+impl<'p, T, S, U> Iterator for CapturesIter<'p, T, S>
+where
+    T: Token,
+    S: Searcher<Item = U>,
+    U: Borrow<T> + Clone,
+{
+    type Item = Captures;
+
+    fn next(&mut self) -> Option<Captures> {
+        while !self.done {
+            let caps = match self.search() {
+                Some(saved) => self.prog.captures_from(saved),
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            };
+            let m = match caps.get_match() {
+                Some(m) => m,
+                None => {
+                    self.done = true;
+                    return Some(caps);
+                }
+            };
+            if m.is_empty() && self.last_end == Some(m.end()) {
+                // an empty match touching the previous match; try again from the next token
+                self.done = !self.input.restart_after_next();
+                continue;
+            }
+            self.last_end = Some(m.end());
+            self.input.restart_at(m.end());
+            if m.is_empty() {
+                // searching from the same position would find the same empty match again
+                self.done = !self.input.restart_after_next();
+            }
+            return Some(caps);
+        }
+        None
+    }
+}
+
+impl<'p, T, S, U> CapturesIter<'p, T, S>
+where
+    T: Token,
+    S: Searcher<Item = U>,
+    U: Borrow<T> + Clone,
+{
+    /// Finds the next match from the start position of the input, as `exec_searcher` would.
+    /// After each step, drops the tokens which can't be needed again: the next search starts at
+    /// the end of the match found by this one, which is no earlier than its start.
+    fn search(&mut self) -> Option<SaveList> {
+        let semantics = self.prog.search_semantics();
+        self.prog.start_run(&mut self.cache, semantics);
+        let mut idx = self.input.start();
+        let mut prev = self.input.prev();
+        let mut saves = Vec::new();
+        loop {
+            let (next_idx, next) = self.input.next();
+            let more = self.prog.exec_step(
+                &mut self.cache,
+                idx,
+                prev.as_ref().map(Borrow::borrow),
+                next.as_ref().map(Borrow::borrow),
+                &mut saves,
+                None,
+            );
+            if !more {
+                return saves.pop();
+            }
+            prev = next;
+            idx = next_idx;
+            let starts = saves
+                .iter()
+                .filter_map(|saved| saved.first().cloned().flatten());
+            let keep = match starts.chain(self.cache.earliest_start()).min() {
+                Some(start) => start.min(idx),
+                None => idx,
+            };
+            self.input.drop_before(keep);
+        }
+    }
+}
+
+/// An iterator over the successive non-overlapping matches of a program. Created by
+/// `Program::find_iter`.
+pub struct FindIter<'p, T: Token, S: Searcher>(CapturesIter<'p, T, S>);
+
+impl<'p, T, S, U> Iterator for FindIter<'p, T, S>
+where
+    T: Token,
+    S: Searcher<Item = U>,
+    U: Borrow<T> + Clone,
+{
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        self.0.next().as_ref().and_then(Captures::get_match)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{CompileOptions, Regex};
+    use crate::captures::Match;
+    use crate::program::{Program, Semantics};
+    use crate::searcher::{IterSearcher, Searcher};
+    use crate::tests::{compile, compile_with_semantics};
+
+    fn spans(matches: impl Iterator<Item = Match>) -> Vec<(usize, usize)> {
+        matches.map(|m| (m.start(), m.end())).collect()
+    }
+
+    #[test]
+    fn find() {
+        let prog = compile(r"a+|b");
+        assert!(prog.is_match("xxaab"));
+        assert!(!prog.is_match("xyz"));
+        // leftmost-first, even though the program reports every match from `exec`
+        assert_eq!(prog.find("xxaab"), Some(Match::new(2, 4)));
+        assert_eq!(prog.find("xyz"), None);
+        let caps = compile(r"(a)(b)?").captures("cac").unwrap();
+        assert_eq!(caps, vec![Some(1), Some(2), Some(1), Some(2), None, None]);
+
+        // the program's semantics are kept otherwise
+        let prog = compile_with_semantics("a|ab", Semantics::LeftmostLongest);
+        assert_eq!(prog.find("xab"), Some(Match::new(1, 3)));
+    }
+
+    #[test]
+    fn find_iter() {
+        let prog = compile(r"a+|b");
+        assert_eq!(
+            spans(prog.find_iter("aabxab")),
+            vec![(0, 2), (2, 3), (4, 5), (5, 6)]
+        );
+        // byte offsets for strings, token positions for slices
+        let prog = compile("é+");
+        assert_eq!(spans(prog.find_iter("aéébé")), vec![(1, 5), (6, 8)]);
+        let haystack = "aéébé".chars().collect::<Vec<_>>();
+        assert_eq!(spans(prog.find_iter(&*haystack)), vec![(1, 3), (4, 5)]);
+
+        // assertions see the tokens before the restart position
+        let prog = compile(r"\ba");
+        assert_eq!(spans(prog.find_iter("aaa a")), vec![(0, 1), (4, 5)]);
+        let prog = compile(r"^a");
+        assert_eq!(spans(prog.find_iter("aaa")), vec![(0, 1)]);
+
+        // anchored programs find adjacent matches from the start of the input
+        let options = CompileOptions {
+            anchored: true,
+            ..Default::default()
+        };
+        let prog = Regex::parse("ab").unwrap().compile_with(&options).unwrap();
+        assert_eq!(spans(prog.find_iter("ababxab")), vec![(0, 2), (2, 4)]);
+
+        let caps = compile("([a-z])=([a-z])?")
+            .captures_iter("a=b c= d=e")
+            .map(|caps| caps.get(2))
+            .collect::<Vec<_>>();
+        assert_eq!(caps, vec![Some(2..3), None, Some(9..10)]);
+    }
+
+    #[test]
+    fn empty_matches() {
+        assert_eq!(
+            spans(compile("a*").find_iter("baaac")),
+            vec![(0, 0), (1, 4), (5, 5)]
+        );
+        assert_eq!(
+            spans(compile("").find_iter("ab")),
+            vec![(0, 0), (1, 1), (2, 2)]
+        );
+        assert_eq!(spans(compile("|a").find_iter("a")), vec![(0, 0), (1, 1)]);
+        assert_eq!(spans(compile("a*?").find_iter("")), vec![(0, 0)]);
+    }
+
+    /// A searcher which counts how many tokens have been read from it.
+    struct Counting<'a, S> {
+        searcher: S,
+        count: &'a mut usize,
+    }
+
+    impl<'a, S: Searcher> Searcher for Counting<'a, S> {
+        type Item = S::Item;
+
+        fn next(&mut self) -> (usize, Option<S::Item>) {
+            let next = self.searcher.next();
+            if next.1.is_some() {
+                *self.count += 1;
+            }
+            next
+        }
+    }
+
+    #[test]
+    fn is_match() {
+        let count_reads = |prog: &Program<char>, haystack: &str| {
+            let haystack = haystack.chars().collect::<Vec<_>>();
+            let mut count = 0;
+            let input = Counting {
+                searcher: IterSearcher::new(haystack.iter()),
+                count: &mut count,
+            };
+            (prog.is_match(input), count)
+        };
+        let haystack = "x".repeat(100) + "ab" + &"x".repeat(100);
+        // stops at the first `Match`, even though a longer match is possible. The token after the
+        // match is read to evaluate assertions before the match is reached.
+        assert_eq!(count_reads(&compile("ab|abx+"), &haystack), (true, 103));
+        assert_eq!(count_reads(&compile("a|b"), "bab"), (true, 2));
+        assert_eq!(count_reads(&compile("c"), &haystack), (false, 202));
+        // programs which can only match at the start of the input stop once that fails
+        assert_eq!(count_reads(&compile("^xa"), &haystack), (false, 2));
+        assert_eq!(count_reads(&compile("^x+b|^b"), &haystack), (false, 101));
+        assert!(count_reads(&compile("^x|a"), &haystack).0);
+        assert!(count_reads(&compile("(?:^x)*a"), &haystack).0);
+        // assertions at the end of the input
+        assert!(compile(r"b\b").is_match("ab"));
+        assert!(!compile(r"b\B").is_match("ab"));
+        assert!(compile("$").is_match(""));
+
+        // programs without save slots
+        let prog = crate::program![
+            :l0 JSplit(l1),
+            Any,
+            Jump(l0),
+            :l1 Token('a'),
+            Split(l2),
+            Token('b'),
+            :l2 WordBoundary,
+            Match,
+        ];
+        assert!(prog.is_match("xab c"));
+        assert!(prog.is_match("xa"));
+        assert!(!prog.is_match("xac"));
+        assert_eq!(prog.exec("xab c"), vec![vec![None]]);
+    }
+
+    #[test]
+    fn lazy() {
+        let prog = compile("ab");
+        let haystack = "xab".repeat(100).chars().collect::<Vec<_>>();
+        let mut count = 0;
+        let input = Counting {
+            searcher: IterSearcher::new(haystack.iter()),
+            count: &mut count,
+        };
+        assert_eq!(spans(prog.find_iter(input).take(2)), vec![(1, 3), (4, 6)]);
+        assert!(count < 10, "read {} tokens", count);
+
+        // tokens are dropped once no search could need them again, and a failed search ends the
+        // iteration
+        let prog = compile("ab+c");
+        let haystack = "ab".to_string() + &"b".repeat(1000) + "c" + &"x".repeat(1000);
+        let mut iter = prog.captures_iter(&*haystack);
+        assert_eq!(iter.next().unwrap().get(0), Some(0..1003));
+        assert!(iter.next().is_none());
+        assert!(iter.input.start() >= 2003);
+        assert!(iter.done);
+        assert!(iter.next().is_none());
+    }
+}
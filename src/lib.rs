@@ -1,19 +1,24 @@
 #[cfg(test)]
 mod tests {
     use super::ast;
+    use super::program::{Program, Semantics};
 
-    fn index_match<'a, H>(
-        haystack: &'a H,
-        saves: &[crate::program::SaveList],
-        match_: usize,
-        subgroup: usize,
-    ) -> &'a H::Output
-    where
-        H: std::ops::Index<std::ops::Range<usize>> + ?Sized,
-    {
-        let start = saves[match_][subgroup * 2].unwrap();
-        let end = saves[match_][subgroup * 2 + 1].unwrap();
-        &haystack[start..end]
+    /// Parses and compiles a pattern with the default options. Shared by the tests of the other
+    /// modules.
+    pub(crate) fn compile(pattern: &str) -> Program<char> {
+        ast::Regex::parse(pattern).unwrap().compile().unwrap()
+    }
+
+    /// Parses and compiles a pattern with the default options, except for its semantics.
+    pub(crate) fn compile_with_semantics(pattern: &str, semantics: Semantics) -> Program<char> {
+        let options = ast::CompileOptions {
+            semantics,
+            ..Default::default()
+        };
+        ast::Regex::parse(pattern)
+            .unwrap()
+            .compile_with(&options)
+            .unwrap()
     }
 
     #[test]
@@ -48,13 +53,19 @@ mod tests {
             Match,
         ];
         let haystack = "ducabc";
+        // by default, only the highest-priority match is reported
+        assert_eq!(
+            program.exec(haystack),
+            vec![vec![Some(3), Some(6), Some(3), Some(5), Some(5), Some(6)]]
+        );
+        let program = program.with_semantics(Semantics::AllPaths);
         let saves = program.exec(haystack);
-        assert_eq!(index_match(haystack, &saves, 0, 0), "abc");
-        assert_eq!(index_match(haystack, &saves, 0, 1), "ab");
-        assert_eq!(index_match(haystack, &saves, 0, 2), "c");
-        assert_eq!(index_match(haystack, &saves, 1, 0), "abc");
-        assert_eq!(index_match(haystack, &saves, 1, 1), "a");
-        assert_eq!(index_match(haystack, &saves, 1, 2), "bc");
+        assert_eq!(saves[0].slice(haystack, 0).unwrap(), "abc");
+        assert_eq!(saves[0].slice(haystack, 1).unwrap(), "ab");
+        assert_eq!(saves[0].slice(haystack, 2).unwrap(), "c");
+        assert_eq!(saves[1].slice(haystack, 0).unwrap(), "abc");
+        assert_eq!(saves[1].slice(haystack, 1).unwrap(), "a");
+        assert_eq!(saves[1].slice(haystack, 2).unwrap(), "bc");
         assert_eq!(
             saves,
             vec![
@@ -89,31 +100,32 @@ mod tests {
             // end of match
             Save(1),
             Match,
-        ];
+        ]
+        .with_semantics(Semantics::AllPaths);
         // the search string contains characters with 1, 2, 3, and 4 byte representations,
         // respectively (U+0024, U+00A2, U+20AC, U+10348)
         let haystack = "$¢€𐍈";
         let saves = program.exec(haystack);
-        assert_eq!(index_match(haystack, &saves, 0, 0), "$¢€");
-        assert_eq!(index_match(haystack, &saves, 0, 1), "$");
-        assert_eq!(index_match(haystack, &saves, 0, 2), "¢");
-        assert_eq!(index_match(haystack, &saves, 0, 3), "€");
-        assert_eq!(index_match(haystack, &saves, 1, 0), "¢€𐍈");
-        assert_eq!(index_match(haystack, &saves, 1, 1), "¢");
-        assert_eq!(index_match(haystack, &saves, 1, 2), "€");
-        assert_eq!(index_match(haystack, &saves, 1, 3), "𐍈");
+        assert_eq!(saves[0].slice(haystack, 0).unwrap(), "$¢€");
+        assert_eq!(saves[0].slice(haystack, 1).unwrap(), "$");
+        assert_eq!(saves[0].slice(haystack, 2).unwrap(), "¢");
+        assert_eq!(saves[0].slice(haystack, 3).unwrap(), "€");
+        assert_eq!(saves[1].slice(haystack, 0).unwrap(), "¢€𐍈");
+        assert_eq!(saves[1].slice(haystack, 1).unwrap(), "¢");
+        assert_eq!(saves[1].slice(haystack, 2).unwrap(), "€");
+        assert_eq!(saves[1].slice(haystack, 3).unwrap(), "𐍈");
 
         // with vec instead of &str
         let haystack = "$¢€𐍈".chars().collect::<Vec<char>>();
         let saves = program.exec(&*haystack);
-        assert_eq!(index_match(&haystack, &saves, 0, 0), &['$', '¢', '€']);
-        assert_eq!(index_match(&haystack, &saves, 0, 1), &['$']);
-        assert_eq!(index_match(&haystack, &saves, 0, 2), &['¢']);
-        assert_eq!(index_match(&haystack, &saves, 0, 3), &['€']);
-        assert_eq!(index_match(&haystack, &saves, 1, 0), &['¢', '€', '𐍈']);
-        assert_eq!(index_match(&haystack, &saves, 1, 1), &['¢']);
-        assert_eq!(index_match(&haystack, &saves, 1, 2), &['€']);
-        assert_eq!(index_match(&haystack, &saves, 1, 3), &['𐍈']);
+        assert_eq!(saves[0].slice(&haystack, 0).unwrap(), &['$', '¢', '€']);
+        assert_eq!(saves[0].slice(&haystack, 1).unwrap(), &['$']);
+        assert_eq!(saves[0].slice(&haystack, 2).unwrap(), &['¢']);
+        assert_eq!(saves[0].slice(&haystack, 3).unwrap(), &['€']);
+        assert_eq!(saves[1].slice(&haystack, 0).unwrap(), &['¢', '€', '𐍈']);
+        assert_eq!(saves[1].slice(&haystack, 1).unwrap(), &['¢']);
+        assert_eq!(saves[1].slice(&haystack, 2).unwrap(), &['€']);
+        assert_eq!(saves[1].slice(&haystack, 3).unwrap(), &['𐍈']);
     }
 
     #[test]
@@ -121,18 +133,28 @@ mod tests {
         use crate::ast::Regex::*;
         // /(ab?)(b?c)a\b/
         let tree = Concat(vec![
-            Capture(Box::new(Concat(vec![
-                Literal(vec!['a']),
-                Repeat(Box::new(Literal(vec!['b'])), ast::Repeater::ZeroOrOne(true)),
-            ]))),
-            Capture(Box::new(Concat(vec![
-                Repeat(Box::new(Literal(vec!['b'])), ast::Repeater::ZeroOrOne(true)),
-                Literal(vec!['c']),
-            ]))),
+            Capture(
+                Box::new(Concat(vec![
+                    Literal(vec!['a']),
+                    Repeat(Box::new(Literal(vec!['b'])), ast::Repeater::ZeroOrOne(true)),
+                ])),
+                None,
+            ),
+            Capture(
+                Box::new(Concat(vec![
+                    Repeat(Box::new(Literal(vec!['b'])), ast::Repeater::ZeroOrOne(true)),
+                    Literal(vec!['c']),
+                ])),
+                None,
+            ),
             WordBoundary,
         ]);
-        let prog = tree.compile();
-        let saves = prog.exec("ducabc ");
+        let prog = tree.compile().unwrap();
+        assert_eq!(
+            prog.exec("ducabc "),
+            vec![vec![Some(3), Some(6), Some(3), Some(5), Some(5), Some(6)]]
+        );
+        let saves = prog.with_semantics(Semantics::AllPaths).exec("ducabc ");
         assert_eq!(
             saves,
             vec![
@@ -141,10 +163,658 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn ast_range() {
+        let prog = compile_with_semantics("b(a){2,3}", Semantics::AllPaths);
+        let saves = prog.exec("baaaa");
+        assert_eq!(
+            saves,
+            vec![
+                vec![Some(0), Some(3), Some(2), Some(3)],
+                vec![Some(0), Some(4), Some(3), Some(4)],
+            ]
+        );
+        let prog = compile_with_semantics("a{2,}?b", Semantics::AllPaths);
+        let saves = prog.exec("aaab");
+        assert_eq!(saves, vec![vec![Some(0), Some(4)], vec![Some(1), Some(4)]]);
+    }
+
+    #[test]
+    fn ast_sets() {
+        let prog = compile_with_semantics("[^aeiou ][a-z0-9]", Semantics::AllPaths);
+        let saves = prog.exec("ab c1 Xy");
+        assert_eq!(saves, vec![vec![Some(3), Some(5)], vec![Some(6), Some(8)]]);
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    struct Word {
+        text: &'static str,
+        pos: &'static str,
+    }
+
+    impl crate::token::Token for Word {
+        fn is_word(&self) -> bool {
+            self.pos != "PUNCT"
+        }
+
+        fn is_line_terminator(&self) -> bool {
+            self.text == "."
+        }
+    }
+
+    fn sentence(words: &[(&'static str, &'static str)]) -> Vec<Word> {
+        words
+            .iter()
+            .map(|&(text, pos)| Word { text, pos })
+            .collect()
+    }
+
+    #[test]
+    fn ast_class() {
+        use crate::ast::Regex::*;
+        use crate::class::Class as TokenClass;
+        let det = TokenClass::new("DET", |w: &Word| w.pos == "DET");
+        let noun = TokenClass::new("NOUN", |w: &Word| w.pos == "NOUN");
+        // /DET? NOUN+/
+        let tree = Concat(vec![
+            Repeat(Box::new(Class(det)), ast::Repeater::ZeroOrOne(true)),
+            Repeat(
+                Box::new(Class(noun.clone())),
+                ast::Repeater::OneOrMore(true),
+            ),
+        ]);
+        let prog = tree.compile().unwrap().with_semantics(Semantics::AllPaths);
+        let words = sentence(&[
+            ("the", "DET"),
+            ("cat", "NOUN"),
+            ("sat", "VERB"),
+            ("on", "ADP"),
+            ("mats", "NOUN"),
+        ]);
+        let saves = prog.exec(&*words);
+        assert!(saves.iter().any(|s| s.get(0) == Some(0..2)));
+        assert!(saves.iter().any(|s| s.get(0) == Some(4..5)));
+        assert!(!saves
+            .iter()
+            .any(|s| s.slots()[0] == Some(2) || s.slots()[0] == Some(3)));
+
+        // programs containing classes can still be compared, by class name
+        let program = super::program![Class(noun), Match,];
+        let same_name = TokenClass::new("NOUN", |_: &Word| true);
+        assert_eq!(
+            program,
+            crate::program::Program::new(
+                vec![
+                    crate::program::Instr::Class(same_name),
+                    crate::program::Instr::Match
+                ],
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn anchors() {
+        let prog = compile_with_semantics("^a|b$", Semantics::AllPaths);
+        assert_eq!(
+            prog.exec("aab"),
+            vec![vec![Some(0), Some(1)], vec![Some(2), Some(3)]]
+        );
+        assert!(prog.exec("ba").is_empty());
+
+        // anchored programs only find matches starting at the beginning of the input
+        let options = ast::CompileOptions {
+            anchored: true,
+            ..Default::default()
+        };
+        let anchored = |pattern| {
+            ast::Regex::parse(pattern)
+                .unwrap()
+                .compile_with(&options)
+                .unwrap()
+        };
+        let prog = anchored("a+").with_semantics(Semantics::AllPaths);
+        assert_eq!(
+            prog.exec("aaba"),
+            vec![vec![Some(0), Some(1)], vec![Some(0), Some(2)]]
+        );
+
+        // validating a whole sequence
+        let prog = anchored("(a|b)+$");
+        assert_eq!(
+            prog.exec("abab"),
+            vec![vec![Some(0), Some(4), Some(3), Some(4)]]
+        );
+        assert!(prog.exec("abcab").is_empty());
+        assert!(prog.exec("").is_empty());
+    }
+
+    #[test]
+    fn line_anchors() {
+        use crate::ast::Regex::*;
+        // /^a|b$/ in multi-line mode
+        let tree = Alternate(vec![
+            Concat(vec![StartOfLine, Literal(vec!['a'])]),
+            Concat(vec![Literal(vec!['b']), EndOfLine]),
+        ]);
+        let parsed = ast::Regex::parse("(?m)^a|b$").unwrap();
+        assert_eq!(parsed.compile().unwrap(), tree.clone().compile().unwrap());
+        let prog = tree.compile().unwrap().with_semantics(Semantics::AllPaths);
+        assert_eq!(
+            prog.exec("ab\nab\nba"),
+            vec![
+                vec![Some(0), Some(1)],
+                vec![Some(1), Some(2)],
+                vec![Some(3), Some(4)],
+                vec![Some(4), Some(5)],
+            ]
+        );
+
+        // sentences as lines
+        use crate::class::Class as TokenClass;
+        let pos = |name: &'static str| Class(TokenClass::new(name, move |w: &Word| w.pos == name));
+        let words = sentence(&[
+            ("the", "DET"),
+            ("cat", "NOUN"),
+            ("sat", "VERB"),
+            (".", "PUNCT"),
+            ("a", "DET"),
+            ("dog", "NOUN"),
+            ("ran", "VERB"),
+            (".", "PUNCT"),
+        ]);
+        let prog = Concat(vec![StartOfLine, pos("DET")])
+            .compile()
+            .unwrap()
+            .with_semantics(Semantics::AllPaths);
+        assert_eq!(
+            prog.exec(&*words),
+            vec![vec![Some(0), Some(1)], vec![Some(4), Some(5)]]
+        );
+        let prog = Concat(vec![pos("VERB"), EndOfLine])
+            .compile()
+            .unwrap()
+            .with_semantics(Semantics::AllPaths);
+        assert_eq!(
+            prog.exec(&*words),
+            vec![vec![Some(2), Some(3)], vec![Some(6), Some(7)]]
+        );
+    }
+
+    #[test]
+    fn word_boundaries() {
+        // a boundary before the match doesn't consume the first token
+        let prog = compile_with_semantics(r"\b(ab)\b", Semantics::AllPaths);
+        let haystack = "x ab abc ab";
+        let saves = prog.exec(haystack);
+        assert_eq!(
+            saves,
+            vec![
+                vec![Some(2), Some(4), Some(2), Some(4)],
+                vec![Some(9), Some(11), Some(9), Some(11)],
+            ]
+        );
+        assert_eq!(saves[0].slice(haystack, 1).unwrap(), "ab");
+
+        // saves on either side of a boundary are at the same position
+        let prog = ast::Regex::parse(r"(a)\b( )").unwrap().compile().unwrap();
+        assert_eq!(
+            prog.exec("ba a"),
+            vec![vec![Some(1), Some(3), Some(1), Some(2), Some(2), Some(3)]]
+        );
+
+        let prog = ast::Regex::parse(r"\B(b)").unwrap().compile().unwrap();
+        assert_eq!(
+            prog.exec("ab b"),
+            vec![vec![Some(1), Some(2), Some(1), Some(2)]]
+        );
+        let prog = ast::Regex::parse(r"a\B").unwrap().compile().unwrap();
+        assert_eq!(prog.exec("a ab a"), vec![vec![Some(2), Some(3)]]);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        use super::token::Token;
+        assert_eq!('A'.fold(), 'a');
+        assert_eq!('ς'.fold(), 'Σ'.fold());
+        assert_eq!('ẞ'.fold(), 'ß');
+        assert_eq!('1'.fold(), '1');
+
+        let prog = ast::Regex::parse("(?i:abc)def").unwrap().compile().unwrap();
+        assert!(prog.is_match("xABcdef"));
+        assert!(!prog.is_match("abcDEF"));
+        let prog = ast::Regex::parse("(?i)[xσ]+[^a]")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(prog.find("XA σςΣb").unwrap().range(), 3..10);
+        // ranges match whichever case they are written in
+        let prog = ast::Regex::parse("(?i)[a-z]+").unwrap().compile().unwrap();
+        assert_eq!(prog.find("1ABc2").unwrap().range(), 1..4);
+        let prog = ast::Regex::parse("(?i)[A-Z_]+").unwrap().compile().unwrap();
+        assert_eq!(prog.find("1aB_c2").unwrap().range(), 1..5);
+        let prog = ast::Regex::parse("(?i)[^a-z]").unwrap().compile().unwrap();
+        assert_eq!(prog.find("aBc2").unwrap().range(), 3..4);
+
+        let options = ast::CompileOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        let prog = ast::Regex::parse("straße(?-i:x)")
+            .unwrap()
+            .compile_with(&options)
+            .unwrap();
+        assert!(prog.is_match("STRAẞEx"));
+        assert!(!prog.is_match("STRAẞEX"));
+
+        // `Map` instructions, which the compiler doesn't produce
+        let prog = super::program![
+            Fold(Map('a' => l0, 'b' => l1)),
+            Reject,
+            :l0 Fold(Token('x')),
+            :l1 Match,
+        ];
+        assert!(prog.is_match("AX"));
+        assert!(prog.is_match("B"));
+        assert!(!prog.is_match("c"));
+    }
+
+    #[test]
+    fn dot_flag() {
+        let prog = ast::Regex::parse("a.b").unwrap().compile().unwrap();
+        assert_eq!(prog.find_iter("a\nb axb").count(), 1);
+        let prog = ast::Regex::parse("(?s)a.b|(?-s:c.)")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            prog.find_iter("a\nb c\nc!")
+                .map(|m| m.range())
+                .collect::<Vec<_>>(),
+            vec![0..3, 6..8]
+        );
+        let options = ast::CompileOptions {
+            dot_matches_line_terminator: true,
+            ..Default::default()
+        };
+        let prog = ast::Regex::parse("a.b")
+            .unwrap()
+            .compile_with(&options)
+            .unwrap();
+        assert!(prog.is_match("a\nb"));
+
+        // a hand-built `Any` matches every token, whatever the flags
+        let tree = ast::Regex::Flags(
+            ast::Flags {
+                dot_matches_line_terminator: Some(false),
+                ..Default::default()
+            },
+            Box::new(ast::Regex::Any),
+        );
+        assert_eq!(
+            tree.compile().unwrap().exec("\n"),
+            vec![vec![Some(0), Some(1)]]
+        );
+    }
+
+    #[test]
+    fn size_limit() {
+        let options = ast::CompileOptions {
+            size_limit: Some(100),
+            ..Default::default()
+        };
+        let tree = ast::Regex::parse("a{50}").unwrap();
+        assert!(tree.clone().compile_with(&options).is_ok());
+        let tree = ast::Regex::parse("(a{50}){2}").unwrap();
+        assert_eq!(
+            tree.compile_with(&options),
+            Err(ast::CompileError::SizeLimitExceeded(100))
+        );
+        // the default limit still catches nested repetitions
+        let tree = ast::Regex::parse("(a{2000}){2000}").unwrap();
+        assert_eq!(
+            tree.compile(),
+            Err(ast::CompileError::SizeLimitExceeded(1 << 20))
+        );
+        // repeating a sub-expression which compiles to nothing takes no time, however many times
+        let prog = ast::Regex::parse("a(?:){100000000000}b(?:){3,100000000000}")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(prog.find("xab").unwrap().range(), 1..3);
+        let tree = ast::Regex::Repeat(
+            Box::new(ast::Regex::Empty),
+            ast::Repeater::Range {
+                min: usize::MAX,
+                max: None,
+                greedy: true,
+            },
+        );
+        assert!(tree.compile().unwrap().is_match(""));
+        // but not if it saves a group
+        let tree = ast::Regex::parse("(){100000000000}").unwrap();
+        assert_eq!(
+            tree.compile(),
+            Err(ast::CompileError::SizeLimitExceeded(1 << 20))
+        );
+    }
+
+    #[test]
+    fn named_groups() {
+        let prog = ast::Regex::parse("(?<key>[a-z]+)=(?:(?<num>[0-9]+)|([a-z]+))")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(prog.group_index("key"), Some(1));
+        assert_eq!(prog.group_index("num"), Some(2));
+        assert_eq!(prog.group_index("other"), None);
+        let haystack = "x ab=12 c=d";
+        let caps = prog.captures_iter(haystack).collect::<Vec<_>>();
+        assert_eq!(caps[0].name("key"), Some(2..4));
+        assert_eq!(caps[0].name("num"), caps[0].get(2));
+        assert_eq!(caps[1].name("num"), None);
+        assert_eq!(caps[1].slice(haystack, 3), Some("d"));
+        assert_eq!(prog.replace_all(haystack, "${num}$3:$key"), "x 12:ab d:c");
+
+        // names must be unique
+        use crate::ast::Regex::*;
+        let tree = Concat(vec![
+            Capture(Box::new(Literal(vec!['a'])), Some("a".to_string())),
+            Capture(Box::new(Literal(vec!['a'])), Some("a".to_string())),
+        ]);
+        assert_eq!(
+            tree.compile(),
+            Err(ast::CompileError::DuplicateGroupName("a".to_string()))
+        );
+
+        // hand-built programs name the group of a `Save` slot
+        let prog = super::program![Save(0), Save(2, "first"), Any, Save(3), Save(1), Match,];
+        assert_eq!(prog.group_index("first"), Some(1));
+        assert_eq!(prog.exec("ab")[0].name("first"), Some(0..1));
+    }
+
+    #[test]
+    fn leftmost_first() {
+        // only the highest-priority parse of the leftmost match is reported, where `AllPaths`
+        // semantics report both
+        let prog = compile(r"(ab?)(b?c)\b");
+        assert_eq!(prog.semantics(), Semantics::LeftmostFirst);
+        assert_eq!(
+            prog.exec("ducabc"),
+            vec![vec![Some(3), Some(6), Some(3), Some(5), Some(5), Some(6)]]
+        );
+
+        // alternatives are tried in order, and quantifiers prefer to repeat unless lazy
+        assert_eq!(compile("a|ab").exec("ab"), vec![vec![Some(0), Some(1)]]);
+        assert_eq!(compile("ab|a").exec("ab"), vec![vec![Some(0), Some(2)]]);
+        assert_eq!(compile("a+").exec("baaab"), vec![vec![Some(1), Some(4)]]);
+        assert_eq!(compile("a+?").exec("baaab"), vec![vec![Some(1), Some(2)]]);
+        assert_eq!(compile("b|a+").exec("aab"), vec![vec![Some(0), Some(2)]]);
+        assert!(compile("c").exec("aab").is_empty());
+
+        // loops which can match the empty string terminate
+        let saves = compile("(a*)*").exec("b");
+        assert_eq!(saves.len(), 1);
+        assert_eq!(saves[0].get(0), Some(0..0));
+
+        // ambiguous patterns don't multiply the number of threads
+        let haystack = "a".repeat(40);
+        assert!(compile("(a|a)*b").exec(&*haystack).is_empty());
+        assert_eq!(
+            compile("(a|a)*").exec(&*haystack),
+            vec![vec![Some(0), Some(40), Some(39), Some(40)]]
+        );
+
+        // programs built by hand can opt in too
+        let prog = super::program![
+            :l0 JSplit(l1),
+            Any,
+            Jump(l0),
+            :l1 Save(0),
+            Split(l2),
+            Token('a'),
+            :l2 Token('b'),
+            Save(1),
+            Match,
+        ]
+        .with_semantics(Semantics::LeftmostFirst);
+        assert_eq!(prog.exec("cabab"), vec![vec![Some(1), Some(3)]]);
+    }
+
+    #[test]
+    fn leftmost_longest() {
+        let longest = |pattern| compile_with_semantics(pattern, Semantics::LeftmostLongest);
+        let first = |pattern| compile_with_semantics(pattern, Semantics::LeftmostFirst);
+
+        // the longest alternative wins, regardless of the order of the `Split`
+        assert_eq!(first("a|ab").exec("ab"), vec![vec![Some(0), Some(1)]]);
+        assert_eq!(longest("a|ab").exec("ab"), vec![vec![Some(0), Some(2)]]);
+        assert_eq!(longest("ab|a").exec("ab"), vec![vec![Some(0), Some(2)]]);
+        // even for lazy quantifiers
+        assert_eq!(first("a*?").exec("aaa"), vec![vec![Some(0), Some(0)]]);
+        assert_eq!(longest("a*?").exec("aaa"), vec![vec![Some(0), Some(3)]]);
+        // but an earlier match beats a longer one
+        assert_eq!(longest("b|aab").exec("xbaab"), vec![vec![Some(1), Some(2)]]);
+        assert!(longest("c").exec("aab").is_empty());
+
+        // each group is as long as possible, in order
+        let pattern = "(a|ab)(c|bcd)(d*)";
+        assert_eq!(
+            first(pattern).exec("abcd"),
+            vec![vec![
+                Some(0),
+                Some(4),
+                Some(0),
+                Some(1),
+                Some(1),
+                Some(4),
+                Some(4),
+                Some(4)
+            ]]
+        );
+        assert_eq!(
+            longest(pattern).exec("abcd"),
+            vec![vec![
+                Some(0),
+                Some(4),
+                Some(0),
+                Some(2),
+                Some(2),
+                Some(3),
+                Some(3),
+                Some(4)
+            ]]
+        );
+        assert_eq!(
+            longest("(a*)(a*)").exec("aa"),
+            vec![vec![Some(0), Some(2), Some(0), Some(2), Some(2), Some(2)]]
+        );
+        // a repeated group reports its last iteration, after each iteration in turn has been as
+        // long as possible, as in POSIX
+        assert_eq!(
+            longest("(a|aa)*").exec("aaa"),
+            vec![vec![Some(0), Some(3), Some(2), Some(3)]]
+        );
+        assert_eq!(
+            longest("(a+)+").exec("aa"),
+            vec![vec![Some(0), Some(2), Some(0), Some(2)]]
+        );
+        assert_eq!(
+            longest("(a|ab|bc|c)*").exec("abc"),
+            vec![vec![Some(0), Some(3), Some(2), Some(3)]]
+        );
+        assert_eq!(
+            longest("(ab|a)+").exec("aba"),
+            vec![vec![Some(0), Some(3), Some(2), Some(3)]]
+        );
+        assert_eq!(
+            longest("(a|ab)(bc|c)*").exec("abcbc"),
+            vec![vec![Some(0), Some(5), Some(0), Some(2), Some(3), Some(5)]]
+        );
+
+        // loops which can match the empty string terminate, and ambiguous patterns don't
+        // multiply the number of threads
+        let saves = longest("(a*)*").exec("b");
+        assert_eq!(saves[0].get(0), Some(0..0));
+        let haystack = "a".repeat(40);
+        assert!(longest("(a|a)*b").exec(&*haystack).is_empty());
+        assert_eq!(
+            longest("(a|a)*").exec(&*haystack),
+            vec![vec![Some(0), Some(40), Some(39), Some(40)]]
+        );
+    }
+
+    #[test]
+    fn empty_loops() {
+        use crate::ast::Repeater::*;
+        use crate::program::Semantics::*;
+
+        // loops whose body can match the empty string terminate with every semantics
+        for &semantics in &[AllPaths, LeftmostFirst, LeftmostLongest] {
+            for &greedy in &[true, false] {
+                let empty = ast::Regex::Repeat(Box::new(ast::Regex::Empty), ZeroOrMore(greedy));
+                let prog = empty.compile().unwrap().with_semantics(semantics);
+                assert_eq!(prog.exec("")[0], vec![Some(0), Some(0)]);
+                assert_eq!(prog.find("ab").unwrap().range(), 0..0);
+            }
+            for pattern in &["(a*)*", "(a*)*?", "(|a)+", "(a|)*b", "(a*)+$", "((a)|b*)*c"] {
+                let prog = compile_with_semantics(pattern, semantics);
+                assert!(prog.is_match("aabc"));
+                prog.all_parses("aabc", None);
+            }
+        }
+
+        // an empty iteration at the end of a loop doesn't set the group
+        assert_eq!(
+            compile_with_semantics("(a*)*", LeftmostFirst).exec("b"),
+            vec![vec![Some(0), Some(0), None, None]]
+        );
+        assert_eq!(
+            compile_with_semantics("(a*)*", LeftmostFirst).exec("aab"),
+            vec![vec![Some(0), Some(2), Some(0), Some(2)]]
+        );
+        assert_eq!(
+            compile_with_semantics("(a|)*b", AllPaths).exec("ab"),
+            vec![
+                vec![Some(0), Some(2), Some(0), Some(1)],
+                vec![Some(1), Some(2), None, None]
+            ]
+        );
+        // unless it's the only iteration
+        assert_eq!(
+            compile_with_semantics("(|a)+", LeftmostFirst).exec("aa"),
+            vec![vec![Some(0), Some(0), Some(0), Some(0)]]
+        );
+        assert_eq!(
+            compile_with_semantics("(|a)+", LeftmostLongest).exec("aa"),
+            vec![vec![Some(0), Some(2), Some(1), Some(2)]]
+        );
+        assert_eq!(
+            compile_with_semantics("((a)|b*)*c", AllPaths).all_parses("abc", None)[0],
+            vec![Some(0), Some(3), Some(1), Some(2), Some(0), Some(1)]
+        );
+    }
+
+    #[test]
+    fn long_alternation() {
+        use crate::program::Semantics::*;
+        // a long chain of `Split`s, followed on a thread with a small stack
+        let run = || {
+            let words = (0..20_000u32)
+                .map(|i| std::char::from_u32(0x4e00 + i).unwrap())
+                .map(|c| ast::Regex::Literal(vec![c, c]))
+                .collect::<Vec<_>>();
+            let re = ast::Regex::Capture(Box::new(ast::Regex::Alternate(words)), None);
+            let haystack = "x\u{9c1f}\u{9c1f}";
+            for &semantics in &[AllPaths, LeftmostFirst, LeftmostLongest] {
+                let prog = re.clone().compile().unwrap().with_semantics(semantics);
+                assert!(prog.is_match(haystack));
+                let caps = prog.captures(haystack).unwrap();
+                assert_eq!(caps.get(0), Some(1..7));
+                assert_eq!(caps.get(1), Some(1..7));
+            }
+        };
+        std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn all_parses() {
+        let span = |start, end| vec![Some(start), Some(end)];
+
+        // both parses of the same match, in priority order
+        let prog = compile(r"(ab?)(b?c)\b");
+        let haystack = "ducabc";
+        let parses = prog.all_parses(haystack, None);
+        assert_eq!(parses.len(), 2);
+        assert_eq!(parses[0].slice(haystack, 1).unwrap(), "ab");
+        assert_eq!(parses[1].slice(haystack, 1).unwrap(), "a");
+
+        // paths which only differ in unsaved branches give a single parse
+        let prog = compile("(?:a|a)(?:b|b)");
+        assert_eq!(prog.all_parses("ab", None), vec![span(0, 2)]);
+        // and are merged before they reach a thread, so they don't multiply
+        let prog = compile("(?:|){1000}b");
+        assert!(prog.all_parses("cc", None).is_empty());
+        assert_eq!(prog.exec("cb"), vec![span(1, 2)]);
+
+        // grouped by span, even though the later match ends first, whatever the semantics of the
+        // program
+        let prog = compile("abc|b");
+        assert_eq!(prog.exec("abc"), vec![span(0, 3)]);
+        assert_eq!(prog.all_parses("abc", None), vec![span(0, 3), span(1, 2)]);
+
+        // the earliest-ending parses are kept under a limit
+        let prog = compile("a*");
+        assert_eq!(prog.all_parses("aaa", None).len(), 10);
+        assert_eq!(
+            prog.all_parses("aaa", Some(3)),
+            vec![span(0, 0), span(0, 1), span(1, 1)]
+        );
+        assert!(prog.all_parses("aaa", Some(0)).is_empty());
+        let prog = compile("(a|aa)*");
+        let haystack = "a".repeat(30);
+        assert_eq!(prog.all_parses(&*haystack, Some(100)).len(), 100);
+        // but the limit doesn't bound the paths followed before the first parse is found
+        let prog = compile("(a*)(a*)(a*)(a*)b");
+        let haystack = "a".repeat(10);
+        assert!(prog.all_parses(&*haystack, Some(1)).is_empty());
+        let haystack = haystack + "b";
+        assert_eq!(prog.all_parses(&*haystack, None).len(), 1001);
+        assert_eq!(
+            prog.all_parses(&*haystack, Some(1)),
+            vec![vec![
+                Some(0),
+                Some(11),
+                Some(0),
+                Some(10),
+                Some(10),
+                Some(10),
+                Some(10),
+                Some(10),
+                Some(10),
+                Some(10)
+            ]]
+        );
+    }
 }
 
 pub mod ast;
+pub mod captures;
+pub mod class;
+pub mod find;
+pub mod matcher;
+pub mod parse;
+pub mod pool;
+pub mod print;
 pub mod program;
 pub mod program_macro;
+pub mod replace;
 pub mod searcher;
+pub mod split;
 pub mod token;
@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+
+use crate::captures::Captures;
+use crate::program::{Cache, Program, SaveList, Semantics};
+use crate::token::Token;
+
+impl<T: Token> Program<T> {
+    /// Returns a `Matcher` which runs the program over tokens as they are fed to it, rather than
+    /// over an input which is available up front.
+    pub fn matcher(&self) -> Matcher<'_, T> {
+        let mut cache = Cache::new(self);
+        self.start_run(&mut cache, self.semantics());
+        Matcher {
+            prog: self,
+            cache,
+            buf: VecDeque::new(),
+            buf_start: 0,
+            buf_prev: None,
+            idx: 0,
+            last_end: None,
+            done: false,
+            saves: Vec::new(),
+        }
+    }
+}
+
+/// Runs a program over a stream of tokens, fed to it one at a time. Created by
+/// `Program::matcher`.
+///
+/// A match is reported by the `feed` call which makes it certain, which is at the earliest the
+/// one after the match ends, since the token after a match is needed to evaluate assertions such
+/// as `WordBoundary` at its end. Matches which are still undecided at the end of the stream are
+/// reported by `finish`. Indices count the tokens fed so far.
+///
+/// With `AllPaths` semantics, the matches are the same as `exec` would find over the whole
+/// stream, and each is reported as soon as it's found. With leftmost semantics, the matches are
+/// the successive non-overlapping matches that `captures_iter` would find: each is reported once
+/// no higher-priority thread could replace it, and the search then starts again at its end, going
+/// back over any tokens fed since. Tokens are only kept for as long as a match could end before
+/// them.
+pub struct Matcher<'p, T: Token> {
+    prog: &'p Program<T>,
+    cache: Cache,
+    /// The tokens fed which may still be needed, either by the current run, or by a run started
+    /// at the end of a match.
+    buf: VecDeque<T>,
+    /// The index of the first token in `buf`.
+    buf_start: usize,
+    /// The token before `buf_start`, or `None` at the start of the input.
+    buf_prev: Option<T>,
+    /// The index of the next position of the current run.
+    idx: usize,
+    /// The end of the previous match, if any. Only used with leftmost semantics.
+    last_end: Option<usize>,
+    /// Whether no more matches can be found.
+    done: bool,
+    /// The matches found by the current run but not yet reported.
+    saves: Vec<SaveList>,
+}
+
+impl<'p, T: Token> Matcher<'p, T> {
+    /// Feeds the next token of the input to the matcher. Returns the matches which have become
+    /// certain.
+    pub fn feed(&mut self, tok: T) -> Vec<Captures> {
+        if self.done {
+            return Vec::new();
+        }
+        self.buf.push_back(tok);
+        self.run(false)
+    }
+
+    /// Feeds each token of `toks` to the matcher in turn. Returns the matches which have become
+    /// certain.
+    pub fn feed_all(&mut self, toks: impl IntoIterator<Item = T>) -> Vec<Captures> {
+        let mut found = Vec::new();
+        for tok in toks {
+            found.append(&mut self.feed(tok));
+            if self.done {
+                break;
+            }
+        }
+        found
+    }
+
+    /// Ends the input, and returns the matches which hadn't been reported yet. Threads waiting on
+    /// the token after the last one, to evaluate an assertion or to reach `Match`, are resolved
+    /// as at the end of the input.
+    pub fn finish(mut self) -> Vec<Captures> {
+        if self.done {
+            return Vec::new();
+        }
+        self.run(true)
+    }
+
+    /// Whether no more matches can be found, however the input goes on. Once this is true, any
+    /// further tokens are ignored.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// The number of tokens fed to the matcher, up to the point where it was done.
+    pub fn position(&self) -> usize {
+        self.buf_start + self.buf.len()
+    }
+
+    /// Runs the program over the tokens fed since the current run last stopped, and then over the
+    /// end of the input if `at_end`. Returns the matches which have become certain.
+    fn run(&mut self, at_end: bool) -> Vec<Captures> {
+        let prog = self.prog;
+        let mut found = Vec::new();
+        while !self.done {
+            let i = self.idx - self.buf_start;
+            let next = self.buf.get(i);
+            if next.is_none() && !at_end {
+                break;
+            }
+            let prev = match i {
+                0 => self.buf_prev.as_ref(),
+                i => self.buf.get(i - 1),
+            };
+            let more = prog.exec_step(&mut self.cache, self.idx, prev, next, &mut self.saves, None);
+            if next.is_some() {
+                self.idx += 1;
+            }
+            if prog.semantics() == Semantics::AllPaths {
+                found.extend(self.saves.drain(..).map(|saved| prog.captures_from(saved)));
+                self.done = !more;
+            } else if !more {
+                // the run has stopped, so the best match it found, if any, is certain
+                match self.saves.pop() {
+                    Some(saved) => found.extend(self.decide(saved)),
+                    None => self.done = true,
+                }
+            }
+        }
+        self.trim();
+        found
+    }
+
+    /// Takes the match decided by a run with leftmost semantics, and starts a new run at its end,
+    /// as `captures_iter` does. Returns the match, unless it's an empty match touching the
+    /// previous one, which is skipped.
+    fn decide(&mut self, saved: SaveList) -> Option<Captures> {
+        let caps = self.prog.captures_from(saved);
+        let m = match caps.get_match() {
+            Some(m) => m,
+            None => {
+                // without the span of the match, there's nowhere to start the next run
+                self.done = true;
+                return Some(caps);
+            }
+        };
+        if m.is_empty() && self.last_end == Some(m.end()) {
+            // an empty match touching the previous match; try again from the next token
+            self.restart(m.end() + 1);
+            return None;
+        }
+        self.last_end = Some(m.end());
+        // starting from the same position after an empty match would find it again
+        self.restart(if m.is_empty() { m.end() + 1 } else { m.end() });
+        Some(caps)
+    }
+
+    /// Starts a new run at index `idx`, or ends the matcher if `idx` is past the end of the
+    /// tokens fed, which only happens after an empty match at the end of the input.
+    fn restart(&mut self, idx: usize) {
+        if idx > self.position() {
+            self.done = true;
+            return;
+        }
+        self.prog.start_run(&mut self.cache, self.prog.semantics());
+        self.idx = idx;
+        self.drop_before(idx);
+    }
+
+    /// Drops the tokens which can't be needed again. Later runs start at the end of a match found
+    /// by the current one, which is no earlier than the start of any match it could still find.
+    fn trim(&mut self) {
+        let mut keep = self.idx;
+        if self.prog.semantics() != Semantics::AllPaths {
+            let starts = self
+                .saves
+                .iter()
+                .filter_map(|saved| saved.first().cloned().flatten());
+            if let Some(start) = starts.chain(self.cache.earliest_start()).min() {
+                keep = keep.min(start);
+            }
+        }
+        self.drop_before(keep);
+    }
+
+    /// Drops the tokens before index `idx`, keeping the last of them as `buf_prev`.
+    fn drop_before(&mut self, idx: usize) {
+        while self.buf_start < idx {
+            match self.buf.pop_front() {
+                Some(tok) => self.buf_prev = Some(tok),
+                None => break,
+            }
+            self.buf_start += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{CompileOptions, Regex};
+    use crate::program::Semantics;
+    use crate::tests::compile_with_semantics;
+
+    #[test]
+    fn streaming() {
+        let prog = compile_with_semantics(r"\ba+\b", Semantics::AllPaths);
+        let mut matcher = prog.matcher();
+        assert!(matcher.feed_all("x aa".chars()).is_empty());
+        // the end of the match is only certain once the next token is seen
+        let found = matcher.feed(' ');
+        assert_eq!(found, vec![vec![Some(2), Some(4)]]);
+        assert!(matcher.feed_all("a".chars()).is_empty());
+        assert_eq!(matcher.position(), 6);
+        // which `finish` stands in for at the end of the input
+        assert_eq!(matcher.finish(), vec![vec![Some(5), Some(6)]]);
+
+        // the same matches as `exec`, in the same order
+        let prog = compile_with_semantics(r"(a|ab)(c|bcd)?\b", Semantics::AllPaths);
+        let haystack = "xab abcd abc a";
+        let mut matcher = prog.matcher();
+        let mut found = matcher.feed_all(haystack.chars());
+        found.append(&mut matcher.finish());
+        assert_eq!(found, prog.exec(haystack));
+    }
+
+    #[test]
+    fn leftmost() {
+        let prog = compile_with_semantics("a+", Semantics::LeftmostFirst);
+        let mut matcher = prog.matcher();
+        // a longer match is still possible
+        assert!(matcher.feed_all("baa".chars()).is_empty());
+        assert_eq!(matcher.feed('b'), vec![vec![Some(1), Some(3)]]);
+        // each match is reported as soon as it's decided, and the search goes on after it
+        assert!(matcher.feed_all("xa".chars()).is_empty());
+        assert_eq!(matcher.feed('x'), vec![vec![Some(5), Some(6)]]);
+        assert!(matcher.feed_all("aaa".chars()).is_empty());
+        assert!(!matcher.is_done());
+        assert_eq!(matcher.position(), 10);
+        assert_eq!(matcher.finish(), vec![vec![Some(7), Some(10)]]);
+
+        // the same matches as `captures_iter`, including empty ones, and matches found again
+        // over tokens which were read past the end of the previous match
+        for &semantics in &[Semantics::LeftmostFirst, Semantics::LeftmostLongest] {
+            for pattern in &["a", "a*", r"(a|ab)(c|bcd)?\b", "ab|abcd|c", "", r"\b"] {
+                let prog = compile_with_semantics(pattern, semantics);
+                for haystack in &["xaxa", "xab abcd abc a", "abcabcd", "baaac", ""] {
+                    let mut matcher = prog.matcher();
+                    let mut found = matcher.feed_all(haystack.chars());
+                    found.append(&mut matcher.finish());
+                    let expected = prog.captures_iter(*haystack).collect::<Vec<_>>();
+                    assert_eq!(found, expected, "{} in {:?}", pattern, haystack);
+                }
+            }
+        }
+
+        // an anchored program stops as soon as it can't match
+        let options = CompileOptions {
+            anchored: true,
+            semantics: Semantics::LeftmostLongest,
+            ..Default::default()
+        };
+        let prog = Regex::parse("ab|abcd")
+            .unwrap()
+            .compile_with(&options)
+            .unwrap();
+        let mut matcher = prog.matcher();
+        assert!(matcher.feed_all("abc".chars()).is_empty());
+        assert_eq!(matcher.feed('x'), vec![vec![Some(0), Some(2)]]);
+        assert!(matcher.is_done());
+        assert!(prog.matcher().finish().is_empty());
+    }
+
+    #[test]
+    fn trim() {
+        // tokens are dropped once no match could end before them
+        let prog = compile_with_semantics("ab+c", Semantics::LeftmostFirst);
+        let mut matcher = prog.matcher();
+        assert!(matcher.feed_all("x".repeat(1000).chars()).is_empty());
+        assert!(matcher.buf.len() <= 1);
+        assert!(matcher.feed_all("ab".chars()).is_empty());
+        assert!(matcher.feed_all("b".repeat(1000).chars()).is_empty());
+        assert_eq!(matcher.buf.len(), 1002);
+        assert!(matcher.feed('c').is_empty());
+        assert_eq!(matcher.feed('x'), vec![vec![Some(1000), Some(2003)]]);
+        assert!(matcher.buf.len() <= 1);
+    }
+}
@@ -0,0 +1,905 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::CharIndices;
+
+use crate::ast::{Flags, Regex, Repeater};
+use crate::class::Ranges;
+
+/// The kind of error encountered while parsing a pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The pattern ended in the middle of a construct, e.g. inside an escape sequence.
+    UnexpectedEnd,
+    /// A `(` with no matching `)`.
+    UnclosedParen,
+    /// A `)` with no matching `(`.
+    UnmatchedCloseParen,
+    /// A `[` with no matching `]`.
+    UnclosedSet,
+    /// A `(?` which isn't followed by a recognized group syntax.
+    UnrecognizedGroup,
+    /// A named group whose name is empty, contains characters other than letters, digits, and
+    /// underscores, starts with a digit, or isn't followed by a `>`.
+    BadGroupName,
+    /// A name used for more than one capturing group.
+    DuplicateGroupName,
+    /// A flag group with an unrecognized or repeated flag, or without any flags, e.g. `(?q)`,
+    /// `(?ii)`, or `(?i-)`.
+    BadFlags,
+    /// A quantifier with nothing before it to repeat, or following another quantifier.
+    DanglingQuantifier,
+    /// A set with no members, i.e. `[]`.
+    EmptySet,
+    /// A set range whose start is greater than its end, e.g. `[z-a]`.
+    BadRange,
+    /// An unrecognized escape sequence.
+    BadEscape,
+    /// A malformed bounded repetition, e.g. `{2,1}` or `{a}`.
+    BadRepetition,
+}
+
+impl ParseErrorKind {
+    fn description(self) -> &'static str {
+        use self::ParseErrorKind::*;
+        match self {
+            UnexpectedEnd => "unexpected end of pattern",
+            UnclosedParen => "unclosed group",
+            UnmatchedCloseParen => "unmatched closing parenthesis",
+            UnclosedSet => "unclosed set",
+            UnrecognizedGroup => "unrecognized group syntax",
+            BadGroupName => "invalid group name",
+            DuplicateGroupName => "duplicate group name",
+            BadFlags => "invalid flags",
+            DanglingQuantifier => "quantifier has nothing to repeat",
+            EmptySet => "empty set",
+            BadRange => "set range is out of order",
+            BadEscape => "unrecognized escape sequence",
+            BadRepetition => "invalid repetition counts",
+        }
+    }
+}
+
+/// An error encountered while parsing a textual pattern. Records the kind of error, along with
+/// the pattern and the byte span of the offending construct within it, so that the error can be
+/// displayed with a caret line pointing at the problem:
+///
+/// ```text
+/// unclosed group
+///     a(b
+///      ^
+/// ```
+///
+/// The pattern is kept as text, so the same error type can be used by any syntax that is parsed
+/// from a string, whatever the token type of the resulting `ast::Regex`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    pattern: String,
+    span: Range<usize>,
+}
+
+impl ParseError {
+    /// Creates a new error of the given kind, for the construct at `span` in `pattern`. The span
+    /// is clamped to the pattern, and its ends moved back to character boundaries, so that the
+    /// error can always be displayed.
+    pub fn new(kind: ParseErrorKind, pattern: &str, span: Range<usize>) -> ParseError {
+        let clamp = |i: usize| {
+            let mut i = i.min(pattern.len());
+            while !pattern.is_char_boundary(i) {
+                i -= 1;
+            }
+            i
+        };
+        let end = clamp(span.end);
+        let start = clamp(span.start).min(end);
+        ParseError {
+            kind,
+            pattern: pattern.to_owned(),
+            span: start..end,
+        }
+    }
+
+    /// The kind of error.
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+
+    /// The pattern in which the error occurred.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// The byte span of the offending construct in the pattern. Errors at the end of the pattern
+    /// have an empty span at `pattern.len()`.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.kind.description())?;
+        // only print the line containing the start of the span, in case the pattern spans
+        // multiple lines
+        let line_start = self.pattern[..self.span.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let line_end = self.pattern[self.span.start..]
+            .find('\n')
+            .map_or(self.pattern.len(), |i| self.span.start + i);
+        let line = &self.pattern[line_start..line_end];
+        // columns are counted in characters rather than bytes, so the caret lines up
+        let col = self.pattern[line_start..self.span.start].chars().count();
+        let width = self.pattern[self.span.start..self.span.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+        writeln!(f, "    {}", line)?;
+        write!(f, "    {:col$}{}", "", "^".repeat(width), col = col)
+    }
+}
+
+impl Error for ParseError {}
+
+/// A recursive descent parser for textual patterns over `char`s.
+///
+/// The grammar is the usual one, from lowest to highest precedence:
+///
+/// ```text
+/// alternate := concat ('|' concat)*
+/// concat    := (repeat | '(?' flags ')')*
+/// repeat    := atom ('*' | '+' | '?' | '{' count (',' count?)? '}') '?'?
+/// atom      := literal | '.' | '^' | '$' | '\b' | '\B' | '\A' | '\z' | '[' set ']'
+///            | '(' alternate ')' | '(?:' alternate ')' | '(?<' name '>' alternate ')'
+///            | '(?' flags ':' alternate ')'
+/// flags     := [imsx]* ('-' [imsx]*)?
+/// ```
+///
+/// A `(?flags)` group applies its flags to the rest of the enclosing group, including any later
+/// branches of an alternation, so `a(?i)b|c` parses the same as `a(?i:b)|(?i:c)`. With the `x`
+/// flag, whitespace and `#` comments are skipped between the items of a concatenation and
+/// before quantifiers. With the `m` flag, `^` and `$` are line anchors rather than input anchors;
+/// `\A` and `\z` always match at the start and end of the input.
+pub(crate) struct Parser<'a> {
+    pattern: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    /// The names of the capturing groups parsed so far.
+    names: HashSet<String>,
+    /// Whether the `x` flag is set at the current position.
+    verbose: bool,
+    /// Whether the `m` flag is set at the current position.
+    multi_line: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(pattern: &'a str) -> Parser<'a> {
+        Parser {
+            pattern,
+            chars: pattern.char_indices().peekable(),
+            names: HashSet::new(),
+            verbose: false,
+            multi_line: false,
+        }
+    }
+
+    /// Parses the entire pattern.
+    pub(crate) fn parse(mut self) -> Result<Regex<char>, ParseError> {
+        let re = self.parse_alternate()?;
+        match self.chars.next() {
+            // `parse_alternate` only stops at the end of the pattern or at a `)`
+            Some((i, _)) => Err(self.error(ParseErrorKind::UnmatchedCloseParen, i..i + 1)),
+            None => Ok(re),
+        }
+    }
+
+    fn error(&self, kind: ParseErrorKind, span: Range<usize>) -> ParseError {
+        ParseError::new(kind, self.pattern, span)
+    }
+
+    /// The byte offset of the next character, or the length of the pattern if there are no more
+    /// characters.
+    fn pos(&mut self) -> usize {
+        match self.chars.peek() {
+            Some(&(i, _)) => i,
+            None => self.pattern.len(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    /// Consumes the next character, or returns an `UnexpectedEnd` error.
+    fn next(&mut self) -> Result<(usize, char), ParseError> {
+        match self.chars.next() {
+            Some(next) => Ok(next),
+            None => {
+                let end = self.pattern.len();
+                Err(self.error(ParseErrorKind::UnexpectedEnd, end..end))
+            }
+        }
+    }
+
+    /// Consumes the next character if it is `c`.
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Skips whitespace and comments, if the `x` flag is set.
+    fn skip_ignored(&mut self) {
+        if !self.verbose {
+            return;
+        }
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while self.chars.next().is_some_and(|(_, c)| c != '\n') {}
+            } else if c.is_whitespace() {
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn parse_alternate(&mut self) -> Result<Regex<char>, ParseError> {
+        let mut branches = Vec::new();
+        // the flags set by `(?flags)` groups in the branches so far, which apply to the later
+        // branches as well
+        let mut flags: Option<Flags> = None;
+        loop {
+            let (e, set) = self.parse_concat()?;
+            let e = match flags {
+                Some(flags) => Regex::Flags(flags, Box::new(e)),
+                None => e,
+            };
+            if let Some(set) = set {
+                flags = Some(flags.map_or(set, |flags| flags.then(set)));
+            }
+            match e {
+                // a group containing only an alternation can be merged into this one
+                Regex::Alternate(es) => branches.extend(es),
+                e => branches.push(e),
+            }
+            if !self.eat('|') {
+                break;
+            }
+        }
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Regex::Alternate(branches))
+        }
+    }
+
+    /// Parses a concatenation. If it contains a `(?flags)` group, the rest of the concatenation is
+    /// wrapped in a `Flags`, and the flags are returned as well, so that they can be applied to
+    /// any later branches of the enclosing alternation.
+    fn parse_concat(&mut self) -> Result<(Regex<char>, Option<Flags>), ParseError> {
+        let mut items = Vec::new();
+        let mut set = None;
+        loop {
+            self.skip_ignored();
+            let (i, c) = match self.chars.peek() {
+                Some(&next) => next,
+                None => break,
+            };
+            let e = match c {
+                '|' | ')' => break,
+                '(' if self.at_flags_group() => {
+                    // consume the `(?`, the flags, and the `)`
+                    self.chars.next();
+                    self.chars.next();
+                    let flags = self.parse_flags(i)?;
+                    self.chars.next();
+                    let (rest, rest_set) = self.parse_concat()?;
+                    push_concat(&mut items, Regex::Flags(flags, Box::new(rest)));
+                    set = Some(rest_set.map_or(flags, |rest_set| flags.then(rest_set)));
+                    break;
+                }
+                // `parse_repeat` consumes any quantifier following an atom, so this is either at
+                // the start of a concatenation, or follows another quantifier
+                '*' | '+' | '?' | '{' => {
+                    if c == '{' {
+                        self.parse_counts(i)?;
+                    } else {
+                        self.chars.next();
+                    }
+                    self.eat('?');
+                    let end = self.pos();
+                    return Err(self.error(ParseErrorKind::DanglingQuantifier, i..end));
+                }
+                _ => self.parse_repeat()?,
+            };
+            push_concat(&mut items, e);
+        }
+        let e = match items.len() {
+            0 => Regex::Empty,
+            1 => items.pop().unwrap(),
+            _ => Regex::Concat(items),
+        };
+        Ok((e, set))
+    }
+
+    /// Whether the next characters are a `(?flags)` group, which applies to the rest of the
+    /// enclosing group, rather than the start of a group with contents of its own.
+    fn at_flags_group(&self) -> bool {
+        let mut ahead = self.chars.clone().map(|(_, c)| c).skip(1);
+        if ahead.next() != Some('?') {
+            return false;
+        }
+        for c in ahead {
+            match c {
+                'i' | 'm' | 's' | 'x' | '-' => {}
+                ')' => return true,
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// Parses the flags of a `(?flags)` or `(?flags:...)` group, after the `(?` starting at byte
+    /// offset `start`, stopping before the `)` or `:`. The `x` and `m` flags take effect
+    /// immediately.
+    fn parse_flags(&mut self, start: usize) -> Result<Flags, ParseError> {
+        let mut flags = Flags::default();
+        // whether the flags being parsed are set rather than cleared, i.e. come before any `-`
+        let mut value = true;
+        // whether there is a flag since the `(?` or the `-`
+        let mut any = false;
+        while let Some(&(i, c)) = self.chars.peek() {
+            let flag = match c {
+                'i' => &mut flags.case_insensitive,
+                'm' => &mut flags.multi_line,
+                's' => &mut flags.dot_matches_line_terminator,
+                'x' => &mut flags.verbose,
+                '-' if value => {
+                    self.chars.next();
+                    value = false;
+                    any = false;
+                    continue;
+                }
+                ')' | ':' => break,
+                _ => return Err(self.error(ParseErrorKind::BadFlags, start..i + c.len_utf8())),
+            };
+            self.chars.next();
+            // each flag can only be given once
+            if flag.is_some() {
+                return Err(self.error(ParseErrorKind::BadFlags, start..i + 1));
+            }
+            *flag = Some(value);
+            any = true;
+        }
+        if !any {
+            let end = self.pos();
+            return Err(self.error(ParseErrorKind::BadFlags, start..end));
+        }
+        if let Some(verbose) = flags.verbose {
+            self.verbose = verbose;
+        }
+        if let Some(multi_line) = flags.multi_line {
+            self.multi_line = multi_line;
+        }
+        Ok(flags)
+    }
+
+    /// Parses an atom, along with the quantifier following it, if any.
+    fn parse_repeat(&mut self) -> Result<Regex<char>, ParseError> {
+        let atom = self.parse_atom()?;
+        self.skip_ignored();
+        let rep = match self.chars.peek() {
+            Some(&(_, c)) if c == '*' || c == '+' || c == '?' => {
+                self.chars.next();
+                self.skip_ignored();
+                let greedy = !self.eat('?');
+                match c {
+                    '*' => Repeater::ZeroOrMore(greedy),
+                    '+' => Repeater::OneOrMore(greedy),
+                    _ => Repeater::ZeroOrOne(greedy),
+                }
+            }
+            Some(&(i, '{')) => {
+                let (min, max) = self.parse_counts(i)?;
+                self.skip_ignored();
+                let greedy = !self.eat('?');
+                Repeater::Range { min, max, greedy }
+            }
+            _ => return Ok(atom),
+        };
+        Ok(Regex::Repeat(Box::new(atom), rep))
+    }
+
+    /// Parses the counts of a bounded repetition, i.e. `{min}`, `{min,}`, or `{min,max}`, starting
+    /// with the `{` at byte offset `start`.
+    fn parse_counts(&mut self, start: usize) -> Result<(usize, Option<usize>), ParseError> {
+        // consume the `{`
+        self.chars.next();
+        let min = self.parse_count(start)?;
+        let max = if self.eat(',') {
+            if self.peek() == Some('}') {
+                None
+            } else {
+                Some(self.parse_count(start)?)
+            }
+        } else {
+            Some(min)
+        };
+        let valid = self.eat('}') && max.is_none_or(|max| min <= max);
+        let end = self.pos();
+        if valid {
+            Ok((min, max))
+        } else {
+            Err(self.error(ParseErrorKind::BadRepetition, start..end))
+        }
+    }
+
+    /// Parses a decimal count in a bounded repetition starting at byte offset `start`.
+    fn parse_count(&mut self, start: usize) -> Result<usize, ParseError> {
+        let mut count: Option<usize> = None;
+        while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+            self.chars.next();
+            let n = count
+                .unwrap_or(0)
+                .checked_mul(10)
+                .and_then(|n| n.checked_add(d as usize));
+            if n.is_none() {
+                let end = self.pos();
+                return Err(self.error(ParseErrorKind::BadRepetition, start..end));
+            }
+            count = n;
+        }
+        count.ok_or_else(|| {
+            let end = self.pos();
+            self.error(ParseErrorKind::BadRepetition, start..end)
+        })
+    }
+
+    fn parse_atom(&mut self) -> Result<Regex<char>, ParseError> {
+        let (i, c) = self.next()?;
+        match c {
+            '.' => Ok(Regex::Dot),
+            '^' if self.multi_line => Ok(Regex::StartOfLine),
+            '$' if self.multi_line => Ok(Regex::EndOfLine),
+            '^' => Ok(Regex::StartOfInput),
+            '$' => Ok(Regex::EndOfInput),
+            '(' => {
+                // flags set inside the group don't apply after it
+                let (verbose, multi_line) = (self.verbose, self.multi_line);
+                let group = if self.eat('?') {
+                    match self.peek() {
+                        Some(':') => {
+                            self.chars.next();
+                            Group::NonCapturing
+                        }
+                        Some('<') | Some('P') => {
+                            if !(self.eat('<') || (self.eat('P') && self.eat('<'))) {
+                                let end = self.pos();
+                                return Err(self.error(ParseErrorKind::UnrecognizedGroup, i..end));
+                            }
+                            Group::Capture(Some(self.parse_group_name(i)?))
+                        }
+                        Some('i') | Some('m') | Some('s') | Some('x') | Some('-') => {
+                            let flags = self.parse_flags(i)?;
+                            if !self.eat(':') {
+                                let end = self.pos();
+                                return Err(self.error(ParseErrorKind::BadFlags, i..end));
+                            }
+                            Group::Flags(flags)
+                        }
+                        _ => {
+                            let end = self.pos();
+                            return Err(self.error(ParseErrorKind::UnrecognizedGroup, i..end));
+                        }
+                    }
+                } else {
+                    Group::Capture(None)
+                };
+                let e = self.parse_alternate()?;
+                self.verbose = verbose;
+                self.multi_line = multi_line;
+                if !self.eat(')') {
+                    return Err(self.error(ParseErrorKind::UnclosedParen, i..i + 1));
+                }
+                Ok(match group {
+                    Group::Capture(name) => Regex::Capture(Box::new(e), name),
+                    Group::NonCapturing => e,
+                    Group::Flags(flags) => Regex::Flags(flags, Box::new(e)),
+                })
+            }
+            '[' => self.parse_set(i),
+            '\\' => match self.parse_escape(i)? {
+                Escape::Literal(c) => Ok(Regex::Literal(vec![c])),
+                Escape::Assertion(e) => Ok(e),
+            },
+            c => Ok(Regex::Literal(vec![c])),
+        }
+    }
+
+    /// Parses the name of a capturing group and the closing `>`, after the `(?<` or `(?P<` starting
+    /// at byte offset `start`. Names consist of letters, digits, and underscores, and can't start
+    /// with a digit.
+    fn parse_group_name(&mut self, start: usize) -> Result<String, ParseError> {
+        let name_start = self.pos();
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.chars.next();
+        }
+        let name_end = self.pos();
+        let name = &self.pattern[name_start..name_end];
+        if !self.eat('>') || name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            let end = self.pos();
+            return Err(self.error(ParseErrorKind::BadGroupName, start..end));
+        }
+        if !self.names.insert(name.to_owned()) {
+            return Err(self.error(ParseErrorKind::DuplicateGroupName, name_start..name_end));
+        }
+        Ok(name.to_owned())
+    }
+
+    /// Parses the body of a set, after the opening `[` at byte offset `start`. Sets without any
+    /// ranges become a `Set` or `NotSet`, and sets with ranges become a `Ranges` class, with
+    /// single characters included as one-character ranges.
+    fn parse_set(&mut self, start: usize) -> Result<Regex<char>, ParseError> {
+        let negated = self.eat('^');
+        let mut members = Vec::new();
+        let mut has_range = false;
+        loop {
+            let lo_start = self.pos();
+            let lo = match self.parse_set_member(start)? {
+                Some(c) => c,
+                None => break,
+            };
+            // a `-` immediately before the closing `]` is a literal
+            if self.peek() == Some('-') {
+                self.chars.next();
+                if self.peek() == Some(']') {
+                    members.push((lo, lo));
+                    members.push(('-', '-'));
+                    continue;
+                }
+                let hi = match self.parse_set_member(start)? {
+                    Some(c) => c,
+                    None => unreachable!("`]` was checked for above"),
+                };
+                if lo > hi {
+                    let end = self.pos();
+                    return Err(self.error(ParseErrorKind::BadRange, lo_start..end));
+                }
+                members.push((lo, hi));
+                has_range = true;
+            } else {
+                members.push((lo, lo));
+            }
+        }
+        if members.is_empty() {
+            let end = self.pos();
+            Err(self.error(ParseErrorKind::EmptySet, start..end))
+        } else if has_range {
+            let ranges = Ranges::new(members);
+            Ok(Regex::Ranges(if negated {
+                ranges.negate()
+            } else {
+                ranges
+            }))
+        } else {
+            let set = members.into_iter().map(|(c, _)| c).collect::<HashSet<_>>();
+            Ok(if negated {
+                Regex::NotSet(set)
+            } else {
+                Regex::Set(set)
+            })
+        }
+    }
+
+    /// Parses a single member of a set, returning `None` at the closing `]`. `start` is the byte
+    /// offset of the opening `[`.
+    fn parse_set_member(&mut self, start: usize) -> Result<Option<char>, ParseError> {
+        match self.chars.next() {
+            None => Err(self.error(ParseErrorKind::UnclosedSet, start..start + 1)),
+            Some((_, ']')) => Ok(None),
+            Some((i, '\\')) => match self.parse_escape(i)? {
+                Escape::Literal(c) => Ok(Some(c)),
+                Escape::Assertion(_) => Err(self.error(ParseErrorKind::BadEscape, i..i + 2)),
+            },
+            Some((_, c)) => Ok(Some(c)),
+        }
+    }
+
+    /// Parses an escape sequence, after the `\` at byte offset `start`.
+    fn parse_escape(&mut self, start: usize) -> Result<Escape, ParseError> {
+        let (i, c) = self.next()?;
+        match c {
+            'b' => Ok(Escape::Assertion(Regex::WordBoundary)),
+            'B' => Ok(Escape::Assertion(Regex::NotWordBoundary)),
+            'A' => Ok(Escape::Assertion(Regex::StartOfInput)),
+            'z' => Ok(Escape::Assertion(Regex::EndOfInput)),
+            'n' => Ok(Escape::Literal('\n')),
+            'r' => Ok(Escape::Literal('\r')),
+            't' => Ok(Escape::Literal('\t')),
+            // any other punctuation, symbol, or whitespace stands for itself
+            c if !c.is_alphanumeric() => Ok(Escape::Literal(c)),
+            c => Err(self.error(ParseErrorKind::BadEscape, start..i + c.len_utf8())),
+        }
+    }
+}
+
+/// The kind of a group, from the syntax at its start.
+enum Group {
+    /// A capturing group, with its name, if any.
+    Capture(Option<String>),
+    NonCapturing,
+    /// A group which sets flags for its contents.
+    Flags(Flags),
+}
+
+/// The meaning of an escape sequence.
+enum Escape {
+    Literal(char),
+    /// A zero-width assertion, which isn't allowed inside sets.
+    Assertion(Regex<char>),
+}
+
+/// Adds an item to a list of concatenated items. Adjacent literals are merged, and nested
+/// concatenations (from non-capturing groups) are flattened, so that the same pattern always parses
+/// to the same tree regardless of grouping.
+fn push_concat(items: &mut Vec<Regex<char>>, e: Regex<char>) {
+    match e {
+        Regex::Empty => {}
+        Regex::Literal(mut toks) => match items.last_mut() {
+            Some(Regex::Literal(prev)) => prev.append(&mut toks),
+            _ => items.push(Regex::Literal(toks)),
+        },
+        Regex::Concat(es) => {
+            for e in es {
+                push_concat(items, e);
+            }
+        }
+        e => items.push(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParseErrorKind;
+    use crate::ast::{Regex, Repeater};
+
+    fn range(min: usize, max: Option<usize>, greedy: bool) -> Repeater {
+        Repeater::Range { min, max, greedy }
+    }
+
+    #[test]
+    fn parse() {
+        use crate::ast::Regex::*;
+        let tree = Regex::parse(r"(ab?)(b?c)\b").unwrap();
+        assert_eq!(
+            tree,
+            Concat(vec![
+                Capture(
+                    Box::new(Concat(vec![
+                        Literal(vec!['a']),
+                        Repeat(Box::new(Literal(vec!['b'])), Repeater::ZeroOrOne(true)),
+                    ])),
+                    None,
+                ),
+                Capture(
+                    Box::new(Concat(vec![
+                        Repeat(Box::new(Literal(vec!['b'])), Repeater::ZeroOrOne(true)),
+                        Literal(vec!['c']),
+                    ])),
+                    None,
+                ),
+                WordBoundary,
+            ])
+        );
+        assert_eq!(
+            Regex::parse("(?<first>a)(?P<second_2>b)").unwrap(),
+            Concat(vec![
+                Capture(Box::new(Literal(vec!['a'])), Some("first".to_string())),
+                Capture(Box::new(Literal(vec!['b'])), Some("second_2".to_string())),
+            ])
+        );
+        assert_eq!(
+            Regex::parse("ab|.+?|[a-c-]|").unwrap(),
+            Alternate(vec![
+                Literal(vec!['a', 'b']),
+                Repeat(Box::new(Dot), Repeater::OneOrMore(false)),
+                Ranges(crate::class::Ranges::new(vec![('a', 'c'), ('-', '-')])),
+                Empty,
+            ])
+        );
+        assert_eq!(
+            Regex::parse("[^ab][a-c_][^a-c_]").unwrap(),
+            Concat(vec![
+                NotSet(['a', 'b'].iter().cloned().collect()),
+                Ranges(crate::class::Ranges::new(vec![('a', 'c'), ('_', '_')])),
+                Ranges(crate::class::Ranges::new(vec![('a', 'c'), ('_', '_')]).negate()),
+            ])
+        );
+        assert_eq!(
+            Regex::parse("a{2}b{1,}?c{0,3}").unwrap(),
+            Concat(vec![
+                Repeat(Box::new(Literal(vec!['a'])), range(2, Some(2), true)),
+                Repeat(Box::new(Literal(vec!['b'])), range(1, None, false)),
+                Repeat(Box::new(Literal(vec!['c'])), range(0, Some(3), true)),
+            ])
+        );
+        assert_eq!(
+            Regex::parse(r"\(\n\\*").unwrap(),
+            Concat(vec![
+                Literal(vec!['(', '\n']),
+                Repeat(Box::new(Literal(vec!['\\'])), Repeater::ZeroOrMore(true)),
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_flags() {
+        use crate::ast::Flags;
+        use crate::ast::Regex::*;
+        let flags = |i, s, x| Flags {
+            case_insensitive: i,
+            dot_matches_line_terminator: s,
+            verbose: x,
+            ..Default::default()
+        };
+        let lit = |s: &str| Literal(s.chars().collect());
+        assert_eq!(
+            Regex::parse("(?i-s:a.)").unwrap(),
+            Flags(
+                flags(Some(true), Some(false), None),
+                Box::new(Concat(vec![lit("a"), Dot]))
+            )
+        );
+        // flags apply to the rest of the group, including later branches
+        assert_eq!(
+            Regex::parse("a(?i)b|c(?s)d|e").unwrap(),
+            Alternate(vec![
+                Concat(vec![
+                    lit("a"),
+                    Flags(flags(Some(true), None, None), Box::new(lit("b")))
+                ]),
+                Flags(
+                    flags(Some(true), None, None),
+                    Box::new(Concat(vec![
+                        lit("c"),
+                        Flags(flags(None, Some(true), None), Box::new(lit("d")))
+                    ]))
+                ),
+                Flags(flags(Some(true), Some(true), None), Box::new(lit("e"))),
+            ])
+        );
+        // but not after the end of the group
+        assert_eq!(
+            Regex::parse("(a(?i)b)c").unwrap(),
+            Concat(vec![
+                Capture(
+                    Box::new(Concat(vec![
+                        lit("a"),
+                        Flags(flags(Some(true), None, None), Box::new(lit("b")))
+                    ])),
+                    None
+                ),
+                lit("c"),
+            ])
+        );
+
+        // verbose mode ignores whitespace and comments, except in sets or when escaped
+        let verbose = Regex::parse(
+            "(?x)
+            (?<year> [0-9]{4} )  # the year
+            -                    # a separator
+            ([0-9] {2} ?) \\ \\# [ ]",
+        )
+        .unwrap();
+        let plain = Regex::parse("(?<year>[0-9]{4})-([0-9]{2}?) #[ ]").unwrap();
+        assert_eq!(
+            verbose,
+            Flags(flags(None, None, Some(true)), Box::new(plain))
+        );
+        // only within the group
+        assert_eq!(
+            Regex::parse("((?x) a ) b").unwrap(),
+            Concat(vec![
+                Capture(
+                    Box::new(Flags(flags(None, None, Some(true)), Box::new(lit("a")))),
+                    None
+                ),
+                lit(" b"),
+            ])
+        );
+
+        // multi-line mode makes `^` and `$` line anchors
+        let multi_line = Flags {
+            multi_line: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            Regex::parse(r"(?m:^a$\A)^").unwrap(),
+            Concat(vec![
+                Flags(
+                    multi_line,
+                    Box::new(Concat(vec![StartOfLine, lit("a"), EndOfLine, StartOfInput]))
+                ),
+                StartOfInput,
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        use super::ParseErrorKind::*;
+        let cases: &[(&str, ParseErrorKind, std::ops::Range<usize>)] = &[
+            ("a(b", UnclosedParen, 1..2),
+            ("ab)", UnmatchedCloseParen, 2..3),
+            ("a|*?", DanglingQuantifier, 2..4),
+            ("a**", DanglingQuantifier, 2..3),
+            ("x[]", EmptySet, 1..3),
+            ("[az-a]", BadRange, 2..5),
+            ("[^]", EmptySet, 0..3),
+            (r"a\q", BadEscape, 1..3),
+            ("[ab", UnclosedSet, 0..1),
+            ("ab\\", UnexpectedEnd, 3..3),
+            ("a{2,1}", BadRepetition, 1..6),
+            ("a{2,x}", BadRepetition, 1..4),
+            ("a{99999999999999999999}", BadRepetition, 1..22),
+            ("{2}", DanglingQuantifier, 0..3),
+            ("a?{2}", DanglingQuantifier, 2..5),
+            ("(?<1a>x)", BadGroupName, 0..6),
+            ("(?<a-b>x)", BadGroupName, 0..4),
+            ("(?<>x)", BadGroupName, 0..4),
+            ("(?Px)", UnrecognizedGroup, 0..3),
+            ("(?<a>x)(?P<a>y)", DuplicateGroupName, 11..12),
+            ("a(?q)", UnrecognizedGroup, 1..3),
+            ("a(?iq)", BadFlags, 1..5),
+            ("(?ii)", BadFlags, 0..4),
+            ("(?i-)", BadFlags, 0..4),
+            ("(?-)", BadFlags, 0..3),
+            ("(?)", BadFlags, 0..2),
+            ("(?i-s-x:a)", BadFlags, 0..6),
+            ("(?i", BadFlags, 0..3),
+        ];
+        for (pattern, kind, span) in cases {
+            let err = Regex::parse(pattern).unwrap_err();
+            assert_eq!(
+                (err.kind(), err.span()),
+                (*kind, span.clone()),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn error_display() {
+        let err = Regex::parse("(a|b").unwrap_err();
+        assert_eq!(err.to_string(), "unclosed group\n    (a|b\n    ^");
+        // columns are counted in characters, and the caret spans the whole construct
+        let err = Regex::parse("é[z-a]").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "set range is out of order\n    é[z-a]\n      ^^^"
+        );
+        // errors at the end of the pattern point just past it
+        let err = Regex::parse("ab\\").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unexpected end of pattern\n    ab\\\n       ^"
+        );
+
+        // spans given from outside the parser are clamped to the pattern
+        use super::{ParseError, ParseErrorKind::*};
+        let err = ParseError::new(BadEscape, "aé", 2..9);
+        assert_eq!(err.span(), 1..3);
+        assert_eq!(
+            err.to_string(),
+            "unrecognized escape sequence\n    aé\n     ^"
+        );
+        assert_eq!(ParseError::new(BadEscape, "ab", 5..9).span(), 2..2);
+    }
+}
@@ -0,0 +1,104 @@
+use std::borrow::Borrow;
+use std::sync::{Mutex, PoisonError};
+
+use crate::captures::Captures;
+use crate::program::{Cache, Program};
+use crate::searcher::IntoSearcher;
+use crate::token::Token;
+
+/// A `Program` which can be shared between threads, along with a pool of `Cache`s for running
+/// it. Each run takes a cache from the pool, or creates one if they are all in use, and puts it
+/// back afterwards. Once there is a cache for each thread which runs the program at the same
+/// time, runs allocate nothing beyond the matches they return.
+#[derive(Debug)]
+pub struct Pool<T: Token> {
+    prog: Program<T>,
+    /// The caches which aren't in use.
+    caches: Mutex<Vec<Cache>>,
+}
+
+impl<T: Token> Pool<T> {
+    /// Creates a pool for running `prog`, with no caches to begin with.
+    pub fn new(prog: Program<T>) -> Pool<T> {
+        Pool {
+            prog,
+            caches: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The program run by the pool.
+    pub fn program(&self) -> &Program<T> {
+        &self.prog
+    }
+
+    /// Executes the program with a cache from the pool. See `Program::exec`.
+    pub fn exec<U: Borrow<T>>(&self, input: impl IntoSearcher<U>) -> Vec<Captures> {
+        self.with_cache(|prog, cache| prog.exec_with(cache, input))
+    }
+
+    /// Calls `f` with the program and a cache from the pool, for running the program some other
+    /// way than `exec`. The cache goes back to the pool once `f` returns, unless it panics.
+    pub fn with_cache<R>(&self, f: impl FnOnce(&Program<T>, &mut Cache) -> R) -> R {
+        // a panic while the lock is held can't leave the list of caches in a bad state
+        let cache = self
+            .caches
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+        let mut cache = cache.unwrap_or_else(|| Cache::new(&self.prog));
+        let result = f(&self.prog, &mut cache);
+        self.caches
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(cache);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::Pool;
+    use crate::program::{Cache, Semantics};
+    use crate::tests::{compile, compile_with_semantics};
+
+    #[test]
+    fn exec_with() {
+        let prog = compile("(a+)(b)?");
+        let other = compile("x");
+        let mut cache = Cache::new(&prog);
+        for haystack in &["aab", "", "xaa", "ab ab"] {
+            assert_eq!(prog.exec_with(&mut cache, *haystack), prog.exec(*haystack));
+            // a cache made for another program still works
+            assert_eq!(
+                other.exec_with(&mut cache, *haystack),
+                other.exec(*haystack)
+            );
+        }
+    }
+
+    #[test]
+    fn pool() {
+        let prog = compile_with_semantics("([a-z]+)=([0-9]+)", Semantics::LeftmostFirst);
+        let pool = Pool::new(prog);
+        thread::scope(|s| {
+            for name in &["a", "b", "c", "d"] {
+                let pool = &pool;
+                s.spawn(move || {
+                    for j in 0..100 {
+                        let haystack = format!("{}={} x", name, j);
+                        let caps = pool.exec(haystack.as_str());
+                        assert_eq!(caps.len(), 1);
+                        assert_eq!(caps[0].slice(&haystack, 2), Some(j.to_string().as_str()));
+                    }
+                });
+            }
+        });
+        // at most one cache per thread was made
+        assert!(pool.caches.lock().unwrap().len() <= 4);
+        let haystack = "ab=1 c=2";
+        let found = pool.with_cache(|prog, cache| prog.exec_with(cache, haystack).len());
+        assert_eq!(found, pool.program().exec(haystack).len());
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::ast::{Flags, Regex, Repeater};
+use crate::class::Ranges;
+use crate::token::Token;
+
+/// Precedence levels of the constructs in the pattern syntax, from loosest to tightest binding. A
+/// sub-expression needs to be wrapped in a non-capturing group if its precedence is lower than the
+/// level required by its context.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Prec {
+    Alternate,
+    Concat,
+    Atom,
+}
+
+/// Formats the tokens of a regex. This allows the same tree walk to be used for `char` patterns,
+/// which need escaping, and for arbitrary tokens formatted by a user-supplied closure.
+trait TokenFormat<T> {
+    /// Writes a token appearing in a `Literal`. If `verbose` is set, the token is inside a group
+    /// with the `x` flag set, where whitespace and `#` are ignored unless escaped.
+    fn literal(&self, tok: &T, verbose: bool, f: &mut fmt::Formatter) -> fmt::Result;
+
+    /// Writes a `Set` or `NotSet`, including its delimiters.
+    fn set(&self, set: &HashSet<T>, negated: bool, f: &mut fmt::Formatter) -> fmt::Result;
+
+    /// Writes a `Ranges` class, including its delimiters.
+    fn ranges(&self, ranges: &Ranges<T>, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+/// Formats a regex over arbitrary tokens, using a closure to format each token. Created by
+/// `Regex::display_with`.
+pub struct DisplayWith<'a, T: Token, F> {
+    regex: &'a Regex<T>,
+    fmt_token: F,
+}
+
+impl<T: Token> Regex<T> {
+    /// Returns a value which displays this regex in pattern syntax, using `fmt_token` to write
+    /// each token. Sets are written as `[...]` with their members in iteration order.
+    pub fn display_with<F>(&self, fmt_token: F) -> DisplayWith<'_, T, F>
+    where
+        F: Fn(&T, &mut fmt::Formatter) -> fmt::Result,
+    {
+        DisplayWith {
+            regex: self,
+            fmt_token,
+        }
+    }
+}
+
+impl<'a, T, F> TokenFormat<T> for DisplayWith<'a, T, F>
+where
+    T: Token,
+    F: Fn(&T, &mut fmt::Formatter) -> fmt::Result,
+{
+    fn literal(&self, tok: &T, _: bool, f: &mut fmt::Formatter) -> fmt::Result {
+        (self.fmt_token)(tok, f)
+    }
+
+    fn set(&self, set: &HashSet<T>, negated: bool, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if negated { "[^" } else { "[" })?;
+        for tok in set {
+            (self.fmt_token)(tok, f)?;
+        }
+        f.write_str("]")
+    }
+
+    fn ranges(&self, ranges: &Ranges<T>, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if ranges.is_negated() { "[^" } else { "[" })?;
+        for (lo, hi) in ranges.ranges() {
+            (self.fmt_token)(lo, f)?;
+            f.write_str("-")?;
+            (self.fmt_token)(hi, f)?;
+        }
+        f.write_str("]")
+    }
+}
+
+impl<'a, T, F> fmt::Display for DisplayWith<'a, T, F>
+where
+    T: Token,
+    F: Fn(&T, &mut fmt::Formatter) -> fmt::Result,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regex(self.regex, Prec::Alternate, Flags::default(), self, f)
+    }
+}
+
+/// Formats `char`s, escaping metacharacters.
+struct CharFormat;
+
+impl CharFormat {
+    /// Characters which need to be escaped outside of sets.
+    const LITERAL_META: &'static str = "\\.[]()|*+?{}^$";
+    /// Characters which need to be escaped inside sets.
+    const SET_META: &'static str = "\\[]^-";
+
+    /// Writes a character, escaping it if it is one of `meta`.
+    fn write(c: char, meta: &str, f: &mut fmt::Formatter) -> fmt::Result {
+        match c {
+            '\n' => f.write_str("\\n"),
+            '\r' => f.write_str("\\r"),
+            '\t' => f.write_str("\\t"),
+            c if meta.contains(c) => write!(f, "\\{}", c),
+            c => write!(f, "{}", c),
+        }
+    }
+}
+
+impl TokenFormat<char> for CharFormat {
+    fn literal(&self, &c: &char, verbose: bool, f: &mut fmt::Formatter) -> fmt::Result {
+        // `\n`, `\r`, and `\t` are always escaped
+        if verbose && (c == '#' || (c.is_whitespace() && !"\n\r\t".contains(c))) {
+            write!(f, "\\{}", c)
+        } else {
+            CharFormat::write(c, CharFormat::LITERAL_META, f)
+        }
+    }
+
+    /// Writes the members of the set in order.
+    fn set(&self, set: &HashSet<char>, negated: bool, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut chars = set.iter().cloned().collect::<Vec<_>>();
+        chars.sort();
+        f.write_str(if negated { "[^" } else { "[" })?;
+        for c in chars {
+            CharFormat::write(c, CharFormat::SET_META, f)?;
+        }
+        f.write_str("]")
+    }
+
+    /// Writes the ranges in their original order, so that parsing the output gives back the same
+    /// list of ranges. Single-character ranges are written as just the character.
+    fn ranges(&self, ranges: &Ranges<char>, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if ranges.is_negated() { "[^" } else { "[" })?;
+        for &(lo, hi) in ranges.ranges() {
+            CharFormat::write(lo, CharFormat::SET_META, f)?;
+            if lo != hi {
+                f.write_str("-")?;
+                CharFormat::write(hi, CharFormat::SET_META, f)?;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+/// Displays the regex in the pattern syntax accepted by `Regex::parse`, with metacharacters
+/// escaped. Parentheses are only added where needed, using non-capturing groups. Parsing the
+/// output gives back an equivalent tree, with the exception of an empty `Set`, which has no
+/// pattern syntax and is written as `[]`, and of a user-defined `Class`, which is written by name
+/// as `\p{name}` but can't be parsed. `Regex::Any` is written as `(?s:.)`, which parses back to
+/// a `Regex::Dot` inside a `Flags`. Outside of the `m` flag, the line anchors are written as
+/// `(?m:^)` and `(?m:$)`, and inside it, the input anchors are written as `\A` and `\z`.
+impl fmt::Display for Regex<char> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regex(self, Prec::Alternate, Flags::default(), &CharFormat, f)
+    }
+}
+
+/// Displays the flags in the syntax of a flag group, without the surrounding `(?` and `)` or `:`,
+/// e.g. `i-s`.
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = [
+            ('i', self.case_insensitive),
+            ('m', self.multi_line),
+            ('s', self.dot_matches_line_terminator),
+            ('x', self.verbose),
+        ];
+        for &(c, _) in flags.iter().filter(|(_, value)| *value == Some(true)) {
+            write!(f, "{}", c)?;
+        }
+        if flags.iter().any(|(_, value)| *value == Some(false)) {
+            f.write_str("-")?;
+        }
+        for &(c, _) in flags.iter().filter(|(_, value)| *value == Some(false)) {
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+/// The precedence of the outermost construct of `re`.
+fn precedence<T: Token>(re: &Regex<T>) -> Prec {
+    match re {
+        Regex::Alternate(es) if es.len() > 1 => Prec::Alternate,
+        Regex::Alternate(es) => es.first().map_or(Prec::Concat, precedence),
+        Regex::Concat(es) if es.len() > 1 => Prec::Concat,
+        Regex::Concat(es) => es.first().map_or(Prec::Concat, precedence),
+        Regex::Literal(toks) if toks.len() != 1 => Prec::Concat,
+        // a quantified expression can't take another quantifier without grouping
+        Regex::Repeat(..) | Regex::Empty => Prec::Concat,
+        Regex::Literal(_) | Regex::Capture(..) | Regex::Flags(..) => Prec::Atom,
+        Regex::Any | Regex::Dot => Prec::Atom,
+        Regex::WordBoundary | Regex::NotWordBoundary => Prec::Atom,
+        Regex::StartOfInput | Regex::EndOfInput => Prec::Atom,
+        Regex::StartOfLine | Regex::EndOfLine => Prec::Atom,
+        Regex::Set(_) | Regex::NotSet(_) | Regex::Ranges(_) | Regex::Class(_) => Prec::Atom,
+    }
+}
+
+/// Writes `re`, wrapped in a non-capturing group if it binds less tightly than `prec`. `mode` is
+/// the flags set at this point by the enclosing `Flags`.
+fn write_regex<T, P>(
+    re: &Regex<T>,
+    prec: Prec,
+    mode: Flags,
+    p: &P,
+    f: &mut fmt::Formatter,
+) -> fmt::Result
+where
+    T: Token,
+    P: TokenFormat<T>,
+{
+    if precedence(re) < prec {
+        f.write_str("(?:")?;
+        write_regex(re, Prec::Alternate, mode, p, f)?;
+        return f.write_str(")");
+    }
+    let verbose = mode.verbose == Some(true);
+    let multi_line = mode.multi_line == Some(true);
+    match re {
+        Regex::Empty => Ok(()),
+        Regex::Literal(toks) => toks.iter().try_for_each(|t| p.literal(t, verbose, f)),
+        Regex::Any => f.write_str("(?s:.)"),
+        Regex::Dot => f.write_str("."),
+        Regex::WordBoundary => f.write_str("\\b"),
+        Regex::NotWordBoundary => f.write_str("\\B"),
+        Regex::StartOfInput => f.write_str(if multi_line { "\\A" } else { "^" }),
+        Regex::EndOfInput => f.write_str(if multi_line { "\\z" } else { "$" }),
+        Regex::StartOfLine => f.write_str(if multi_line { "^" } else { "(?m:^)" }),
+        Regex::EndOfLine => f.write_str(if multi_line { "$" } else { "(?m:$)" }),
+        Regex::Set(set) => p.set(set, false, f),
+        Regex::NotSet(set) => p.set(set, true, f),
+        Regex::Ranges(ranges) => p.ranges(ranges, f),
+        Regex::Class(class) => write!(f, "\\p{{{}}}", class.name()),
+        Regex::Repeat(e, rep) => {
+            write_regex(e, Prec::Atom, mode, p, f)?;
+            let greedy = match *rep {
+                Repeater::ZeroOrOne(greedy) => {
+                    f.write_str("?")?;
+                    greedy
+                }
+                Repeater::ZeroOrMore(greedy) => {
+                    f.write_str("*")?;
+                    greedy
+                }
+                Repeater::OneOrMore(greedy) => {
+                    f.write_str("+")?;
+                    greedy
+                }
+                Repeater::Range { min, max, greedy } => {
+                    match max {
+                        Some(max) if max == min => write!(f, "{{{}}}", min)?,
+                        Some(max) => write!(f, "{{{},{}}}", min, max)?,
+                        None => write!(f, "{{{},}}", min)?,
+                    }
+                    greedy
+                }
+            };
+            if !greedy {
+                f.write_str("?")?;
+            }
+            Ok(())
+        }
+        Regex::Capture(e, name) => {
+            match name {
+                Some(name) => write!(f, "(?<{}>", name)?,
+                None => f.write_str("(")?,
+            }
+            write_regex(e, Prec::Alternate, mode, p, f)?;
+            f.write_str(")")
+        }
+        Regex::Flags(flags, e) => {
+            write!(f, "(?{}:", flags)?;
+            write_regex(e, Prec::Alternate, mode.then(*flags), p, f)?;
+            f.write_str(")")
+        }
+        Regex::Concat(es) => es
+            .iter()
+            .try_for_each(|e| write_regex(e, Prec::Concat, mode, p, f)),
+        Regex::Alternate(es) => {
+            for (i, e) in es.iter().enumerate() {
+                if i > 0 {
+                    f.write_str("|")?;
+                }
+                write_regex(e, Prec::Concat, mode, p, f)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{Regex, Repeater};
+
+    #[test]
+    fn display() {
+        use crate::ast::Regex::*;
+        let tree = Concat(vec![
+            Capture(
+                Box::new(Alternate(vec![
+                    Literal(vec!['a', '.']),
+                    Repeat(
+                        Box::new(Literal(vec!['b', 'c'])),
+                        Repeater::OneOrMore(false),
+                    ),
+                ])),
+                None,
+            ),
+            Repeat(
+                Box::new(Alternate(vec![Dot, Empty])),
+                Repeater::ZeroOrMore(true),
+            ),
+            Set(['-', 'a', 'b', 'c', 'x', ']'].iter().cloned().collect()),
+            WordBoundary,
+        ]);
+        assert_eq!(tree.to_string(), r"(a\.|(?:bc)+?)(?:.|)*[\-\]abcx]\b");
+        assert_eq!(
+            Repeat(Box::new(Any), Repeater::OneOrMore(true)).to_string(),
+            "(?s:.)+"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let patterns = [
+            r"(ab?)(b?c)\b",
+            r"a|b(c|d)*|",
+            r"(?:ab)+?x(?:a|b)?",
+            r"[a-z\-\]]+\.\(\)",
+            r"[^aeiou][^a-z0-9_][xa-c]",
+            r"((a*)*)+",
+            r"\n\t\\\|",
+            r"()",
+            r"(?:)*a",
+            r"^a|b$|^$",
+            r"\b\Ba\b*",
+            r"a{3}(?:bc){2,}?[de]{0,4}",
+            r"(?<year>[0-9]{4})-(?<month>[0-9]{2})",
+            r"a(?i)b|c(?-s:.)",
+            r"(?is-x:a.)(?s)(?i)x|y",
+            r"(?m)^a$|\Ab\z(?-m)^c$",
+            r"(?m:^a)$|((?m)b$)^",
+            "(?x) a\\ b # comment\n [ #]+ \\#",
+        ];
+        for pattern in &patterns {
+            let tree = Regex::parse(pattern).unwrap();
+            let printed = tree.to_string();
+            assert_eq!(Regex::parse(&printed).unwrap(), tree, "{}", printed);
+        }
+    }
+
+    #[test]
+    fn display_flags() {
+        let tree = Regex::parse("(?x) a b | c (?-x) d e").unwrap();
+        assert_eq!(tree.to_string(), "(?x:ab)|(?x:c(?-x: d e))");
+        let tree = Regex::parse("(?s-i)a(?x: \\  \\#b)").unwrap();
+        assert_eq!(tree.to_string(), r"(?s-i:a(?x:\ \#b))");
+        // the anchors are written to suit the `m` flag
+        let tree = Regex::parse(r"(?m)^a\z|b(?-m:$)").unwrap();
+        assert_eq!(tree.to_string(), r"(?m:^a\z)|(?m:b(?-m:$))");
+        let tree = Regex::Concat(vec![Regex::StartOfLine, Regex::EndOfInput]);
+        assert_eq!(tree.to_string(), "(?m:^)$");
+        // which parses back to the anchor inside a `Flags`
+        let parsed = Regex::parse(&tree.to_string()).unwrap();
+        assert_eq!(parsed.compile(), tree.compile());
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    struct Word(&'static str);
+
+    impl crate::token::Token for Word {
+        fn is_word(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn display_with() {
+        let tree = Regex::Concat(vec![
+            Regex::Literal(vec![Word("the"), Word("cat")]),
+            Regex::Repeat(Box::new(Regex::Dot), Repeater::ZeroOrOne(true)),
+        ]);
+        let printed = tree
+            .display_with(|tok, f| write!(f, "<{}>", tok.0))
+            .to_string();
+        assert_eq!(printed, "<the><cat>.?");
+    }
+}
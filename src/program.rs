@@ -1,8 +1,12 @@
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::mem;
-use std::ops::Index;
+use std::ops::{Index, Range};
+use std::sync::Arc;
 
+use crate::captures::Captures;
+use crate::class::{Class, Ranges};
 use crate::searcher::{IntoSearcher, Searcher};
 use crate::token::Token;
 
@@ -10,19 +14,45 @@ use crate::token::Token;
 pub type InstrPtr = usize;
 
 /// A single instruction
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Instr<T: Token> {
     /// Matches a single token.
     Token(T),
     /// Matches any token.
     Any,
+    /// Matches any token which isn't a line terminator.
+    NotLineTerminator,
     /// Maps input tokens to new `InstrPtr`s. If the input token is not found, falls through to the
     /// next instruction.
     Map(HashMap<T, InstrPtr>),
     /// Matches a single token from a set of tokens.
     Set(HashSet<T>),
+    /// Matches a single token not in a set of tokens.
+    NotSet(HashSet<T>),
+    /// Matches a single token in (or, if negated, not in) a set of ranges of tokens.
+    Ranges(Ranges<T>),
+    /// Matches a single token in a user-defined class.
+    Class(Class<T>),
+    /// Runs a `Token`, `Map`, `Set` or `NotSet` instruction against the case folded input token,
+    /// as given by `Token::fold`. Used for case-insensitive matching, so the tokens in the inner
+    /// instruction should already be folded. A `Ranges` instruction is run against the input
+    /// token as it is, after `Token::fold`, and after `Token::fold_variant`, and matches if any
+    /// of them is in the ranges, or, if negated, if none of them are.
+    Fold(Box<Instr<T>>),
     /// Matches a word boundary.
     WordBoundary,
+    /// Matches anywhere except at a word boundary.
+    NotWordBoundary,
+    /// Matches the start of the input.
+    StartOfInput,
+    /// Matches the end of the input.
+    EndOfInput,
+    /// Matches the start of a line, i.e. the start of the input or a position following a line
+    /// terminator.
+    StartOfLine,
+    /// Matches the end of a line, i.e. the end of the input or a position preceding a line
+    /// terminator.
+    EndOfLine,
     /// Splits into two states, preferring not to jump. Used to implement alternations and
     /// quantifiers
     Split(InstrPtr),
@@ -40,99 +70,879 @@ pub enum Instr<T: Token> {
     Match,
 }
 
-/// A thread, consisting of an `InstrPtr` to the current instruction, and a vector of all saved
-/// positions
+impl<T: Token> Instr<T> {
+    /// Returns a copy of this instruction, with any `InstrPtr`s it contains moved forward by
+    /// `offset`. Used to copy a section of a program to a later location.
+    pub(crate) fn relocate(&self, offset: usize) -> Instr<T> {
+        use self::Instr::*;
+        match self {
+            Map(map) => Map(map
+                .iter()
+                .map(|(t, &pc)| (t.clone(), pc + offset))
+                .collect()),
+            Split(pc) => Split(pc + offset),
+            JSplit(pc) => JSplit(pc + offset),
+            Jump(pc) => Jump(pc + offset),
+            Fold(instr) => Fold(Box::new(instr.relocate(offset))),
+            instr => instr.clone(),
+        }
+    }
+}
+
+/// The saved locations of a list of threads, stored one after another in a single vector, with
+/// the same number of slots for each thread. Adding a thread copies its locations into space
+/// which is reused from one input position to the next, rather than allocating a new `SaveList`
+/// for every thread.
 #[derive(Debug)]
-struct Thread {
-    /// Pointer to current instruction
-    pc: InstrPtr,
-    /// Saved positions
-    saved: SaveList,
+struct SlotTable {
+    slots: Vec<Option<usize>>,
+    /// The number of slots per thread.
+    width: usize,
 }
 
-impl Thread {
-    /// Create a new `Thread` with the specified instruction pointer and the given list of saved
-    /// locations.
-    fn new(pc: InstrPtr, saved: SaveList) -> Thread {
-        Thread { pc, saved }
+impl SlotTable {
+    /// Create a new, empty `SlotTable` with `width` slots per thread.
+    fn new(width: usize) -> SlotTable {
+        SlotTable {
+            slots: Vec::new(),
+            width,
+        }
+    }
+
+    /// Adds the saved locations of a new thread at the end of the table.
+    fn push(&mut self, saved: &[Option<usize>]) {
+        self.slots.extend_from_slice(saved);
+    }
+
+    /// Adds a new thread with no saved locations at the end of the table.
+    fn push_unset(&mut self) {
+        self.slots.resize(self.slots.len() + self.width, None);
+    }
+
+    /// The saved locations of the `i`th thread.
+    fn get(&self, i: usize) -> &[Option<usize>] {
+        &self.slots[i * self.width..(i + 1) * self.width]
+    }
+
+    /// The saved locations of the `i`th thread, for overwriting.
+    fn get_mut(&mut self, i: usize) -> &mut [Option<usize>] {
+        &mut self.slots[i * self.width..(i + 1) * self.width]
+    }
+
+    /// Removes every thread, keeping the allocated space.
+    fn clear(&mut self) {
+        self.slots.clear();
+    }
+}
+
+/// A save to a repeated slot, in a list of every save a path has made to the repeated slots of a
+/// program, newest first. Paths which branched after a save share the list up to it, so that
+/// copying a path's history is cheap. Only kept with `LeftmostLongest` semantics, which compare
+/// every iteration of a repeated group, rather than only the last one, which is all the save slots
+/// keep.
+#[derive(Debug)]
+struct History {
+    /// The slot saved to.
+    slot: usize,
+    /// The location saved.
+    idx: usize,
+    /// The number of saves in the list, including this one.
+    len: usize,
+    /// The save made before this one, if any.
+    prev: Option<Arc<History>>,
+}
+
+impl History {
+    /// Adds saves to the given slots, all at `idx`, to the end of a history.
+    fn extend(history: Option<&Arc<History>>, slots: &[usize], idx: usize) -> Option<Arc<History>> {
+        let mut history = history.cloned();
+        for &slot in slots {
+            let len = history.as_ref().map_or(0, |prev| prev.len) + 1;
+            history = Some(Arc::new(History {
+                slot,
+                idx,
+                len,
+                prev: history,
+            }));
+        }
+        history
+    }
+}
+
+impl Drop for History {
+    // drop the earlier saves in a loop, since dropping them recursively could overflow the stack
+    // for a group repeated many times
+    fn drop(&mut self) {
+        let mut prev = self.prev.take();
+        while let Some(history) = prev {
+            prev = match Arc::try_unwrap(history) {
+                Ok(mut history) => history.prev.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+/// The input position at which threads are being added, along with the tokens on either side of
+/// it, used for evaluating assertions.
+struct Context<'t, T> {
+    /// The current input index.
+    idx: usize,
+    /// The token before the current position, or `None` at the start of the input.
+    prev: Option<&'t T>,
+    /// The token after the current position, or `None` at the end of the input.
+    next: Option<&'t T>,
+}
+
+impl<'t, T: Token> Context<'t, T> {
+    /// Returns whether a zero-width assertion holds at the current position.
+    fn assert(&self, instr: &Instr<T>) -> bool {
+        use self::Instr::*;
+        // the start and end of the input count as non-word tokens for word boundaries
+        let word_boundary =
+            || self.prev.is_some_and(T::is_word) != self.next.is_some_and(T::is_word);
+        match instr {
+            StartOfInput => self.prev.is_none(),
+            EndOfInput => self.next.is_none(),
+            StartOfLine => self.prev.is_none_or(T::is_line_terminator),
+            EndOfLine => self.next.is_none_or(T::is_line_terminator),
+            WordBoundary => word_boundary(),
+            NotWordBoundary => !word_boundary(),
+            _ => unreachable!("not an assertion"),
+        }
+    }
+}
+
+/// A set of `InstrPtr`s less than a fixed size, with constant-time insertion, lookup, and
+/// clearing.
+#[derive(Debug)]
+struct SparseSet {
+    /// The members of the set, in insertion order.
+    dense: Vec<InstrPtr>,
+    /// For each member `pc`, the index of `pc` in `dense`. Other entries may have any value.
+    sparse: Vec<usize>,
+}
+
+impl SparseSet {
+    /// Create a new, empty `SparseSet` which can hold values less than `size`
+    fn new(size: usize) -> SparseSet {
+        SparseSet {
+            dense: Vec::with_capacity(size),
+            sparse: vec![0; size],
+        }
+    }
+
+    fn contains(&self, pc: InstrPtr) -> bool {
+        let i = self.sparse[pc];
+        i < self.dense.len() && self.dense[i] == pc
+    }
+
+    /// Adds `pc` to the set, returning whether it was newly added.
+    fn insert(&mut self, pc: InstrPtr) -> bool {
+        if self.contains(pc) {
+            false
+        } else {
+            self.sparse[pc] = self.dense.len();
+            self.dense.push(pc);
+            true
+        }
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
     }
 }
 
 /// A list of threads
 #[derive(Debug)]
 struct ThreadList {
-    threads: Vec<Thread>,
+    /// Pointer to the current instruction of each thread.
+    threads: Vec<InstrPtr>,
+    /// The saved locations of each thread, in the same order as `threads`.
+    slots: SlotTable,
+    /// The histories before the current position of the pending threads which the threads on the
+    /// list were added from, in the order they were added. Only kept with `LeftmostLongest`
+    /// semantics.
+    histories: Vec<Option<Arc<History>>>,
+    /// For each thread, in the same order as `threads`, the index of its history in `histories`,
+    /// and the range of `recents` holding its saves at the current position.
+    thread_histories: Vec<(usize, Range<usize>)>,
+    /// The recent saves of the threads.
+    recents: Vec<usize>,
+    /// The saved locations of the path being followed by `add_thread`.
+    saved: SaveList,
+    /// The index in `histories` of the history of the path being followed by `add_thread`.
+    from: usize,
+    /// The repeated slots which the path being followed by `add_thread` has saved to at the
+    /// current position, in order. These are only added to the history of a thread once it gets
+    /// past the next token, since most threads don't.
+    recent: Vec<usize>,
+    /// How threads which reach the same instruction are merged.
+    semantics: Semantics,
+    /// The instructions and saved locations reached by the paths followed at the current position.
+    /// Only used with `AllPaths` semantics.
+    seen: HashSet<(InstrPtr, SaveList)>,
+    /// Lists taken out of `seen` when it's cleared, to be reused for its next entries.
+    spare: Vec<SaveList>,
+    /// The instructions which have been visited while adding threads at the current position.
+    /// Only used when the program's `Semantics` keep a single thread per instruction.
+    visited: SparseSet,
+    /// For each visited instruction, the saved locations of the preferred path which reached it.
+    /// Only used with `LeftmostLongest` semantics.
+    reached: Vec<SaveList>,
+    /// For each visited instruction, the index of the history, and the recent saves, of the
+    /// preferred path which reached it. Only used with `LeftmostLongest` semantics, and left
+    /// empty until a run with them starts.
+    reached_histories: Vec<(usize, Vec<usize>)>,
+    /// For each visited instruction which is on the list, the index of its thread. Only used with
+    /// `LeftmostLongest` semantics.
+    thread_at: Vec<usize>,
+    /// For each instruction, whether it's on the path currently being followed by `add_thread`.
+    /// Not used with `LeftmostFirst` semantics.
+    on_path: Vec<bool>,
+    /// The frames of the search in progress in `add_thread`, kept between calls to reuse the
+    /// allocation.
+    stack: Vec<Frame>,
 }
 
 impl ThreadList {
-    /// Create a new `ThreadList` with a specified capacity
-    fn new(cap: usize) -> ThreadList {
+    /// Create a new `ThreadList` with a specified capacity, for a program of length `cap` with
+    /// `num_slots` save slots.
+    fn new(cap: usize, num_slots: usize, semantics: Semantics) -> ThreadList {
         ThreadList {
             threads: Vec::with_capacity(cap),
+            slots: SlotTable::new(num_slots),
+            histories: Vec::new(),
+            thread_histories: Vec::new(),
+            recents: Vec::new(),
+            saved: Vec::with_capacity(num_slots),
+            from: 0,
+            recent: Vec::new(),
+            semantics,
+            seen: HashSet::new(),
+            spare: Vec::new(),
+            visited: SparseSet::new(cap),
+            reached: vec![Vec::new(); cap],
+            reached_histories: Vec::new(),
+            thread_at: vec![0; cap],
+            on_path: vec![false; cap],
+            stack: Vec::new(),
+        }
+    }
+
+    /// The number of threads on the list.
+    fn len(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// The instruction and saved locations of the `i`th thread.
+    fn get(&self, i: usize) -> (InstrPtr, &[Option<usize>]) {
+        (self.threads[i], self.slots.get(i))
+    }
+
+    /// The history and recent saves of the `i`th thread, which are empty unless the list has
+    /// `LeftmostLongest` semantics.
+    fn history(&self, i: usize) -> (Option<&Arc<History>>, &[usize]) {
+        match self.thread_histories.get(i) {
+            Some(&(from, ref recent)) => {
+                (self.histories[from].as_ref(), &self.recents[recent.clone()])
+            }
+            None => (None, &[]),
+        }
+    }
+
+    /// Adds a thread at the end of the list, with the history of the path being followed.
+    fn push(&mut self, pc: InstrPtr, saved: &[Option<usize>]) {
+        self.threads.push(pc);
+        self.slots.push(saved);
+        if self.semantics == Semantics::LeftmostLongest {
+            let history = (self.from, self.push_recent());
+            self.thread_histories.push(history);
         }
     }
 
-    /// Add a new `Thread` with the specified instruction pointer, and the given list of saved
-    /// locations. If `pc` points to a `Jump`, `Split`, `JSplit`, or `Save` instruction, calls
-    /// `add_thread` recursively, so that the active `ThreadList` never contains pointers to those
+    /// Copies the recent saves of the path being followed to `recents`, returning their range.
+    fn push_recent(&mut self) -> Range<usize> {
+        let start = self.recents.len();
+        self.recents.extend_from_slice(&self.recent);
+        start..self.recents.len()
+    }
+
+    /// Removes every thread, keeping the allocated space.
+    fn clear(&mut self) {
+        self.threads.clear();
+        self.slots.clear();
+        self.histories.clear();
+        self.thread_histories.clear();
+        self.recents.clear();
+    }
+
+    /// Forget which threads have been added, before adding threads at a new position.
+    fn clear_visited(&mut self) {
+        self.visited.clear();
+        self.spare.extend(self.seen.drain().map(|(_, saved)| saved));
+    }
+
+    /// Add a new thread with the specified instruction pointer, the given list of saved
+    /// locations, and the history at index `from` of `histories`. If `pc` points to a `Jump`,
+    /// `Split`, `JSplit`, or `Save` instruction, or to a zero-width assertion, follows the paths
+    /// through it instead, so that the active `ThreadList` never contains pointers to those
     /// instructions.
+    ///
+    /// The paths are followed depth-first, in priority order, using an explicit stack rather than
+    /// recursion, so that long chains of `Split`s can't overflow the call stack. A single list of
+    /// saved locations is shared by every path: each `Save` pushes a frame which undoes it once
+    /// the paths through it have been followed, and the list is only copied into the `SlotTable`
+    /// when a thread is added. The recent saves to repeated slots are kept the same way.
+    ///
+    /// A path which comes back to an instruction it has already passed through at this position
+    /// has gone round a loop whose body matched the empty string. Going round again could only
+    /// reach the same threads with different saved locations, so the path is dropped, rather than
+    /// looping forever. As a result, the saved locations of an empty iteration at the end of a
+    /// loop are never reported, e.g. `(a*)*` matches the empty string without setting group 1.
     fn add_thread<T: Token>(
         &mut self,
         pc: InstrPtr,
-        in_idx: usize,
+        ctx: &Context<T>,
         prog: &Program<T>,
-        mut saved: SaveList,
+        saved: &[Option<usize>],
+        from: usize,
     ) {
-        // don't check if there's already a thread with this `pc` on the list, because we want to
-        // keep alternate paths alive, in case they produce different submatch values.
+        // take the buffer, so that it can be borrowed alongside the rest of the list
+        let mut saved_buf = mem::take(&mut self.saved);
+        saved_buf.clear();
+        saved_buf.extend_from_slice(saved);
+        let mut saved = saved_buf;
+        self.from = from;
+        self.stack.push(Frame::Follow(pc));
+        while let Some(frame) = self.stack.pop() {
+            match frame {
+                Frame::Follow(pc) => self.follow(pc, ctx, prog, &mut saved),
+                Frame::RestoreSave(slot, old) => saved[slot] = old,
+                Frame::Forget => {
+                    self.recent.pop();
+                }
+                Frame::Leave(pc) => self.on_path[pc] = false,
+            }
+        }
+        self.saved = saved;
+        self.recent.clear();
+    }
+
+    /// Follows the path reaching `pc` with the saved locations `saved`, for `add_thread`. Pushes
+    /// frames for the paths leading on from `pc`, or adds a thread if `pc` consumes a token.
+    fn follow<T: Token>(
+        &mut self,
+        pc: InstrPtr,
+        ctx: &Context<T>,
+        prog: &Program<T>,
+        saved: &mut SaveList,
+    ) {
+        match self.semantics {
+            // don't check if there's already a thread with this `pc` on the list, because we want
+            // to keep alternate paths alive, in case they produce different submatch values.
+            Semantics::AllPaths => {
+                if self.on_path[pc] {
+                    return;
+                }
+                // a path which reached this `pc` with the same saved locations would behave
+                // exactly like this one from here on, and has higher priority, so drop this one.
+                // Checking wherever paths can meet, rather than only when a thread is added, keeps
+                // paths which differ only in unsaved branches from multiplying before they get
+                // there. It also catches loops which don't pass a `Save`, so `on_path` is only
+                // needed for those which do.
+                if prog.merge_points[pc] {
+                    let mut key = self.spare.pop().unwrap_or_default();
+                    key.clone_from(saved);
+                    let key = (pc, key);
+                    if self.seen.contains(&key) {
+                        self.spare.push(key.1);
+                        return;
+                    }
+                    self.seen.insert(key);
+                }
+                self.on_path[pc] = true;
+                self.stack.push(Frame::Leave(pc));
+            }
+            // any thread reaching this `pc` from here on would be lower priority than the one
+            // which reached it first, so it can be dropped.
+            Semantics::LeftmostFirst => {
+                if !self.visited.insert(pc) {
+                    return;
+                }
+            }
+            // only continue if this path is preferred over the one which reached `pc` before it,
+            // if any. Each iteration of a loop which matched the empty string would make the
+            // history of a repeated group longer, and so preferred, so those paths are dropped
+            // with `on_path` instead.
+            Semantics::LeftmostLongest => {
+                if self.on_path[pc] {
+                    return;
+                }
+                let (reached_from, ref reached_recent) = self.reached_histories[pc];
+                if !self.visited.insert(pc)
+                    && cmp_longest(
+                        Path::new(
+                            saved,
+                            self.histories[self.from].as_ref(),
+                            &self.recent,
+                            ctx.idx,
+                        ),
+                        Path::new(
+                            &self.reached[pc],
+                            self.histories[reached_from].as_ref(),
+                            reached_recent,
+                            ctx.idx,
+                        ),
+                        &prog.repeated,
+                    ) != Ordering::Greater
+                {
+                    return;
+                }
+                self.reached[pc].clone_from(saved);
+                let reached = &mut self.reached_histories[pc];
+                reached.0 = self.from;
+                reached.1.clone_from(&self.recent);
+                self.on_path[pc] = true;
+                self.stack.push(Frame::Leave(pc));
+            }
+        }
         use self::Instr::*;
+        // frames are popped in reverse order, so the higher priority path is pushed last
         match prog[pc] {
             Split(split) => {
-                // call `add_thread` recursively
                 // branch with no jump is higher priority
-                // clone the `saved` vector so we can use it again in the second branch
-                self.add_thread(pc + 1, in_idx, prog, saved.clone());
-                self.add_thread(split, in_idx, prog, saved);
+                self.stack.push(Frame::Follow(split));
+                self.stack.push(Frame::Follow(pc + 1));
             }
             JSplit(split) => {
-                // call `add_thread` recursively
                 // branch with jump is higher priority
-                // clone the `saved` vector so we can use it again in the second branch
-                self.add_thread(split, in_idx, prog, saved.clone());
-                self.add_thread(pc + 1, in_idx, prog, saved);
+                self.stack.push(Frame::Follow(pc + 1));
+                self.stack.push(Frame::Follow(split));
             }
             Jump(jump) => {
-                // call `add_thread` recursively
                 // jump to specified pc
-                self.add_thread(jump, in_idx, prog, saved);
+                self.stack.push(Frame::Follow(jump));
             }
             Save(idx) => {
-                // save index
-                saved[idx] = Some(in_idx);
-                // and recursively add next instruction
-                self.add_thread(pc + 1, in_idx, prog, saved);
+                // save index, to be restored once every path from here has been followed
+                self.stack.push(Frame::RestoreSave(idx, saved[idx]));
+                saved[idx] = Some(ctx.idx);
+                if self.semantics == Semantics::LeftmostLongest && prog.repeated[idx] {
+                    self.recent.push(idx);
+                    self.stack.push(Frame::Forget);
+                }
+                self.stack.push(Frame::Follow(pc + 1));
+            }
+            StartOfInput | EndOfInput | StartOfLine | EndOfLine | WordBoundary
+            | NotWordBoundary => {
+                // only continue if the assertion holds at the current position
+                if ctx.assert(&prog[pc]) {
+                    self.stack.push(Frame::Follow(pc + 1));
+                }
             }
             Reject => {} // do nothing, this thread is dead
-            Token(_) | Map(_) | Set(_) | Any | WordBoundary | Match => {
+            Token(_) | Map(_) | Set(_) | NotSet(_) | Ranges(_) | Class(_) | Fold(_) | Any
+            | NotLineTerminator | Match => {
+                match self.semantics {
+                    Semantics::AllPaths | Semantics::LeftmostFirst => {}
+                    // if there's already a thread with this pc, this path is preferred over it,
+                    // so take its place
+                    Semantics::LeftmostLongest => {
+                        let i = self.thread_at[pc];
+                        if self.threads.get(i) == Some(&pc) {
+                            self.slots.get_mut(i).copy_from_slice(saved);
+                            // the replaced thread's recent saves are left unused until the list
+                            // is cleared
+                            self.thread_histories[i] = (self.from, self.push_recent());
+                            return;
+                        }
+                        self.thread_at[pc] = self.threads.len();
+                    }
+                }
                 // push a new thread with the given pc
-                self.threads.push(Thread::new(pc, saved));
+                self.push(pc, saved);
             }
         }
     }
 }
 
-impl<'a> IntoIterator for &'a mut ThreadList {
-    type Item = Thread;
-    type IntoIter = ::std::vec::Drain<'a, Thread>;
+/// Scratch space for running a `Program`, which can be kept and passed to `Program::exec_with`
+/// to avoid allocating it again on every run. Once a cache has grown to fit the inputs it's used
+/// with, running a program allocates nothing beyond the matches it returns.
+///
+/// A cache is created for a particular program. It can be used with any program, but using it
+/// with a program of a different size replaces its contents with new ones of the right size.
+#[derive(Debug)]
+pub struct Cache {
+    /// The threads at the current input position.
+    curr: ThreadList,
+    /// Threads which have advanced past a token, but haven't yet been added to `curr`, since
+    /// that requires knowing the token after them in order to evaluate assertions. Each consists
+    /// of the pc to add, and the saved positions and history, which are kept in separate tables.
+    pending: Vec<InstrPtr>,
+    pending_slots: SlotTable,
+    pending_histories: Vec<Option<Arc<History>>>,
+    /// The history, recent saves, and the location of those saves, of the match kept by a run
+    /// with `LeftmostLongest` semantics, to compare it with later ones.
+    best: (Option<Arc<History>>, Vec<usize>, usize),
+}
+
+impl Cache {
+    /// Creates a cache for running `prog`.
+    pub fn new<T: Token>(prog: &Program<T>) -> Cache {
+        Cache {
+            curr: ThreadList::new(prog.prog.len(), prog.num_slots, prog.semantics),
+            pending: Vec::with_capacity(prog.prog.len()),
+            pending_slots: SlotTable::new(prog.num_slots),
+            pending_histories: Vec::new(),
+            best: (None, Vec::new(), 0),
+        }
+    }
+
+    /// Makes the cache ready for a new run of `prog` with the given semantics, replacing its
+    /// contents if they were made for a program of a different size.
+    fn reset<T: Token>(&mut self, prog: &Program<T>, semantics: Semantics) {
+        if self.curr.reached.len() != prog.prog.len() || self.curr.slots.width != prog.num_slots {
+            *self = Cache::new(prog);
+        }
+        self.curr.semantics = semantics;
+        if semantics == Semantics::LeftmostLongest {
+            self.curr
+                .reached_histories
+                .resize(prog.prog.len(), (0, Vec::new()));
+        }
+        self.curr.clear();
+        self.pending.clear();
+        self.pending_slots.clear();
+        self.pending_histories.clear();
+        self.best.0 = None;
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.threads.drain(..)
+    /// The earliest start of a match, in slot 0, among the threads of a run which are waiting
+    /// for the next position, or `None` if none of them has started a match.
+    pub(crate) fn earliest_start(&self) -> Option<usize> {
+        (0..self.pending.len())
+            .filter_map(|i| self.pending_slots.get(i).first().cloned().flatten())
+            .min()
     }
 }
 
+/// A step of the depth-first search in `ThreadList::add_thread`.
+#[derive(Debug)]
+enum Frame {
+    /// Follow the path which has reached `pc`.
+    Follow(InstrPtr),
+    /// Set a save slot back to the value it had before a `Save`, once every path through the
+    /// `Save` has been followed.
+    RestoreSave(usize, Option<usize>),
+    /// Remove the last of the recent saves to repeated slots, once every path through its `Save`
+    /// has been followed.
+    Forget,
+    /// Mark `pc` as no longer on the current path, once every path through it has been followed.
+    Leave(InstrPtr),
+}
+
 /// A list of saved locations, which may be absent
 pub type SaveList = Vec<Option<usize>>;
 
+/// A path's saved locations, along with the history of its saves to repeated slots: those in
+/// `history`, followed by the `recent` ones, to the given slots at `idx`.
+#[derive(Clone, Copy)]
+struct Path<'a> {
+    saved: &'a [Option<usize>],
+    history: Option<&'a Arc<History>>,
+    recent: &'a [usize],
+    idx: usize,
+}
+
+impl<'a> Path<'a> {
+    fn new(
+        saved: &'a [Option<usize>],
+        history: Option<&'a Arc<History>>,
+        recent: &'a [usize],
+        idx: usize,
+    ) -> Path<'a> {
+        Path {
+            saved,
+            history,
+            recent,
+            idx,
+        }
+    }
+}
+
+/// Compares two paths for `LeftmostLongest` semantics, returning `Greater` if `a` is preferred.
+/// Each pair of slots is compared in turn, preferring an earlier start, then a later end, so that
+/// the whole match is leftmost-longest, then each group is leftmost-longest in order. Locations
+/// which are present are preferred over absent ones. For a pair with a `repeated` slot, the saves
+/// of every iteration are compared in the same way, from the first, so that each iteration is
+/// leftmost-longest in turn, and an extra iteration is preferred.
+fn cmp_longest(a: Path, b: Path, repeated: &[bool]) -> Ordering {
+    let (a_saves, b_saves) = diverged(a, b);
+    for (i, (x, y)) in a.saved.chunks(2).zip(b.saved.chunks(2)).enumerate() {
+        let slots = 2 * i..2 * i + x.len();
+        let ord = if slots.clone().any(|slot| repeated[slot]) {
+            // the saves before the histories diverged are the same for both
+            cmp_saves(
+                saves_to(&a_saves, a, slots.clone()),
+                saves_to(&b_saves, b, slots.clone()),
+            )
+        } else {
+            cmp_saves(
+                slots.clone().zip(x.iter().cloned()),
+                slots.clone().zip(y.iter().cloned()),
+            )
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+/// The saves to the given slots among a list of saves from a path's history, followed by its
+/// recent saves, for `cmp_saves`.
+fn saves_to<'a>(
+    saves: &'a [&'a History],
+    path: Path<'a>,
+    slots: Range<usize>,
+) -> impl Iterator<Item = (usize, Option<usize>)> + 'a {
+    let recent = path.recent.iter().map(move |&slot| (slot, path.idx));
+    saves
+        .iter()
+        .map(|save| (save.slot, save.idx))
+        .chain(recent)
+        .filter(move |(slot, _)| slots.contains(slot))
+        .map(|(slot, idx)| (slot, Some(idx)))
+}
+
+/// Compares two sequences of saves to the slots of a group, for `cmp_longest`.
+fn cmp_saves(
+    a: impl IntoIterator<Item = (usize, Option<usize>)>,
+    b: impl IntoIterator<Item = (usize, Option<usize>)>,
+) -> Ordering {
+    let (mut a, mut b) = (a.into_iter(), b.into_iter());
+    loop {
+        let ord = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some((slot, Some(x))), Some((_, Some(y)))) if slot % 2 == 0 => y.cmp(&x),
+            (Some((_, Some(x))), Some((_, Some(y)))) => x.cmp(&y),
+            (Some((_, x)), Some((_, y))) => x.is_some().cmp(&y.is_some()),
+            // a history without the end of an iteration is still in it, so it will end later
+            // than the one which has ended, but one without the start of another iteration has
+            // fewer of them
+            (Some((slot, _)), None) if slot % 2 == 0 => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (None, Some((slot, _))) if slot % 2 == 0 => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+/// The saves in the histories of two paths since they diverged, oldest first, not including
+/// their recent saves.
+fn diverged<'a>(a: Path<'a>, b: Path<'a>) -> (Vec<&'a History>, Vec<&'a History>) {
+    let len = |history: Option<&Arc<History>>| history.map_or(0, |history| history.len);
+    let (mut x, mut y) = (a.history, b.history);
+    let (mut a_saves, mut b_saves) = (Vec::new(), Vec::new());
+    while let Some(h) = x.filter(|_| len(x) > len(y)) {
+        a_saves.push(&**h);
+        x = h.prev.as_ref();
+    }
+    while let Some(h) = y.filter(|_| len(y) > len(x)) {
+        b_saves.push(&**h);
+        y = h.prev.as_ref();
+    }
+    while let (Some(h), Some(k)) = (x, y) {
+        if Arc::ptr_eq(h, k) {
+            break;
+        }
+        a_saves.push(&**h);
+        x = h.prev.as_ref();
+        b_saves.push(&**k);
+        y = k.prev.as_ref();
+    }
+    a_saves.reverse();
+    b_saves.reverse();
+    (a_saves, b_saves)
+}
+
+/// Adds the saved locations of a thread which reached `Match` to `saves`, according to
+/// `semantics`, keeping the history of the match in `best` for `LeftmostLongest` semantics, as
+/// in `Cache::best`.
+/// Returns whether the remaining threads should be dropped.
+fn add_match(
+    semantics: Semantics,
+    saves: &mut Vec<SaveList>,
+    best: &mut (Option<Arc<History>>, Vec<usize>, usize),
+    path: Path,
+    repeated: &[bool],
+) -> bool {
+    let saved = path.saved;
+    match semantics {
+        Semantics::AllPaths => {
+            saves.push(saved.to_vec());
+            false
+        }
+        // this match is preferred over any previous one, since it comes from a
+        // higher-priority thread. Any remaining threads are lower priority than this one.
+        Semantics::LeftmostFirst => {
+            saves.clear();
+            saves.push(saved.to_vec());
+            true
+        }
+        // threads which started at the same position or earlier may still find a longer
+        // match, so keep running them
+        Semantics::LeftmostLongest => {
+            if saves.first().is_none_or(|best_saved| {
+                let (history, recent, idx) = best;
+                cmp_longest(
+                    path,
+                    Path::new(best_saved, history.as_ref(), recent, *idx),
+                    repeated,
+                ) == Ordering::Greater
+            }) {
+                saves.clear();
+                saves.push(saved.to_vec());
+                best.0 = path.history.cloned();
+                best.1.clear();
+                best.1.extend_from_slice(path.recent);
+                best.2 = path.idx;
+            }
+            false
+        }
+    }
+}
+
+/// Whether a thread with the given saved locations can be dropped, because it can only lead to
+/// matches which are not preferred over the one already found. This is only used for
+/// `LeftmostLongest` semantics, where it drops the threads which started after the match.
+fn is_cut(semantics: Semantics, saves: &[SaveList], saved: &[Option<usize>]) -> bool {
+    if semantics != Semantics::LeftmostLongest {
+        return false;
+    }
+    match saves.first().and_then(|best| best[0]) {
+        Some(start) => saved[0].is_none_or(|s| s > start),
+        None => false,
+    }
+}
+
+/// Finds the instructions at which paths through `prog` can meet, for `Program::merge_points`.
+/// Paths which reach an instruction with the same saved locations either came to it from
+/// different instructions, or only differed in a slot which a `Save` just before it overwrote,
+/// or started from different threads at the same instruction. So these are the instructions with
+/// more than one way in, those following a `Save`, and those where a thread can start: the first
+/// instruction, and those which can follow a token.
+fn merge_points<T: Token>(prog: &[Instr<T>]) -> Vec<bool> {
+    use self::Instr::*;
+    let mut ways_in = vec![0; prog.len()];
+    // counts another way into `pc`, or, with `n = 2`, makes it a merge point regardless
+    let mut enter = |pc: InstrPtr, n: usize| {
+        if let Some(ways_in) = ways_in.get_mut(pc) {
+            *ways_in += n;
+        }
+    };
+    enter(0, 2);
+    for (pc, instr) in prog.iter().enumerate() {
+        let instr = match instr {
+            Fold(instr) => instr,
+            instr => instr,
+        };
+        match *instr {
+            Split(split) | JSplit(split) => {
+                enter(pc + 1, 1);
+                enter(split, 1);
+            }
+            Jump(jump) => enter(jump, 1),
+            StartOfInput | EndOfInput | StartOfLine | EndOfLine | WordBoundary
+            | NotWordBoundary => enter(pc + 1, 1),
+            Save(_) => enter(pc + 1, 2),
+            Map(ref map) => {
+                enter(pc + 1, 2);
+                for &target in map.values() {
+                    enter(target, 2);
+                }
+            }
+            Token(_) | Set(_) | NotSet(_) | Ranges(_) | Class(_) | Fold(_) | Any
+            | NotLineTerminator => enter(pc + 1, 2),
+            Reject | Match => {}
+        }
+    }
+    ways_in.into_iter().map(|ways_in| ways_in > 1).collect()
+}
+
+/// Finds the save slots which a path through `prog` can save to more than once, for
+/// `Program::repeated`. A path can only come back to an instruction by jumping back to it, and a
+/// `Save` always leads on to the next instruction, so a `Save` can only be passed again if some
+/// instruction after it leads back to it or before it.
+fn repeated_slots<T: Token>(prog: &[Instr<T>], num_slots: usize) -> Vec<bool> {
+    use self::Instr::*;
+    // the number of jumps back over each instruction, counted by their ends
+    let mut jumps_back = vec![0isize; prog.len() + 1];
+    let mut jump = |from: InstrPtr, to: InstrPtr| {
+        if to <= from {
+            jumps_back[to] += 1;
+            jumps_back[from + 1] -= 1;
+        }
+    };
+    for (pc, instr) in prog.iter().enumerate() {
+        match *instr {
+            Split(target) | JSplit(target) | Jump(target) => jump(pc, target),
+            Map(ref map) => map.values().for_each(|&target| jump(pc, target)),
+            _ => {}
+        }
+    }
+    let mut repeated = vec![false; num_slots];
+    let mut count = 0;
+    for (pc, instr) in prog.iter().enumerate() {
+        count += jumps_back[pc];
+        if let Save(slot) = *instr {
+            if count > 0 {
+                if let Some(repeated) = repeated.get_mut(slot) {
+                    *repeated = true;
+                }
+            }
+        }
+    }
+    repeated
+}
+
+/// How the VM chooses between the different ways a program can match an input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Semantics {
+    /// Follow every path through the program, and report the saved locations of every path which
+    /// reaches a `Match`, including matches starting at every position in the input, and
+    /// different submatch positions for the same match. Paths which reach the same instruction
+    /// with the same saved locations at the same position are merged wherever they meet, so
+    /// unsaved ambiguity costs nothing, but the number of threads can still grow
+    /// polynomially with the length of the input, with a degree depending on the number of save
+    /// slots. See `Program::all_parses` for a more convenient interface.
+    AllPaths,
+    /// Report only the leftmost match, choosing between paths by the priorities encoded by `Split`
+    /// and `JSplit`, as in Perl or RE2. Only the highest-priority thread at each instruction is
+    /// kept, so this runs in time linear in the length of the input. This is the default.
+    LeftmostFirst,
+    /// Report only the longest of the matches starting at the leftmost position, as in POSIX.
+    /// Between the ways of making that match, each group, in order, takes the leftmost-longest
+    /// position consistent with the groups before it. The iterations of a repeated group are
+    /// each leftmost-longest in turn, from the first, and the group reports its last one, so
+    /// `(a|aa)*` on `aaa` gives group 1 at `2..3`. As with the other semantics, a path which comes
+    /// back to an instruction without consuming a token is dropped, which can rule out the parse
+    /// POSIX prefers when part of a repeated group matches the empty string.
+    ///
+    /// Only the preferred thread at each instruction is kept, so the number of threads is bounded
+    /// by the length of the program. But the paths from an instruction are followed again each
+    /// time a preferred path reaches it, which can make this several times slower than
+    /// `LeftmostFirst` for ambiguous patterns. The saves of every iteration of a repeated group
+    /// are kept while a match is in progress, so they take memory proportional to the number of
+    /// iterations.
+    LeftmostLongest,
+}
+
 /// A program for the VM
 #[derive(Debug, PartialEq)]
 pub struct Program<T: Token> {
@@ -141,139 +951,412 @@ pub struct Program<T: Token> {
     /// Number of save slots. Generally _2n + 2_, where _n_ is the number of capturing groups,
     /// since the first two slots are used for the entire match.
     num_slots: usize,
+    /// Which matches `exec` reports.
+    semantics: Semantics,
+    /// The group numbers of the named capturing groups. Shared with the `Captures` of every
+    /// match.
+    names: Arc<HashMap<String, usize>>,
+    /// For each instruction, whether two paths followed by `add_thread` can reach it with the
+    /// same saved locations, so that `AllPaths` semantics need to check for duplicates there.
+    merge_points: Vec<bool>,
+    /// For each save slot, whether a path can save to it more than once, so that
+    /// `LeftmostLongest` semantics need to keep the history of its saves.
+    repeated: Vec<bool>,
 }
 
 impl<T: Token> Program<T> {
+    /// Creates a program with `LeftmostFirst` semantics.
     pub fn new(prog: Vec<Instr<T>>, num_slots: usize) -> Program<T> {
-        Program { prog, num_slots }
+        Program {
+            merge_points: merge_points(&prog),
+            repeated: repeated_slots(&prog, num_slots),
+            prog,
+            num_slots,
+            semantics: Semantics::LeftmostFirst,
+            names: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the match semantics used by `exec`.
+    pub fn with_semantics(self, semantics: Semantics) -> Program<T> {
+        Program { semantics, ..self }
+    }
+
+    /// The match semantics used by `exec`.
+    pub fn semantics(&self) -> Semantics {
+        self.semantics
+    }
+
+    /// Sets the names of capturing groups, as a map from each name to its group number.
+    pub fn with_names(self, names: HashMap<String, usize>) -> Program<T> {
+        Program {
+            names: Arc::new(names),
+            ..self
+        }
+    }
+
+    /// The group number of the capturing group with the given name, if any.
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        self.names.get(name).cloned()
+    }
+
+    /// Wraps the saved locations of a match, along with the names of the capturing groups.
+    pub(crate) fn captures_from(&self, saved: SaveList) -> Captures {
+        Captures::new(saved, self.names.clone())
+    }
+
+    /// Executes the program. Returns the matches found, according to the program's `Semantics`,
+    /// with the positions of all the save locations of each.
+    pub fn exec<U: Borrow<T>>(&self, input: impl IntoSearcher<U>) -> Vec<Captures> {
+        self.exec_with(&mut Cache::new(self), input)
     }
 
-    /// Executes the program. Returns a vector of matches found. For each match, the positions of
-    /// all the save locations are stored in a vector
-    pub fn exec<U: Borrow<T>>(&self, input: impl IntoSearcher<U>) -> Vec<SaveList> {
-        self.exec_searcher(input.into_searcher())
+    /// Executes the program like `exec`, using `cache` for its scratch space instead of
+    /// allocating it.
+    pub fn exec_with<U: Borrow<T>>(
+        &self,
+        cache: &mut Cache,
+        input: impl IntoSearcher<U>,
+    ) -> Vec<Captures> {
+        self.exec_searcher(cache, input.into_searcher(), 0, None, self.semantics, None)
+            .into_iter()
+            .map(|saved| self.captures_from(saved))
+            .collect()
     }
 
-    /// Executes the program. Returns a vector of matches found. For each match, the positions of
-    /// all the save locations are stored in a vector
-    pub fn exec_iter<U: Borrow<T>>(&self, input: impl IntoIterator<Item = U>) -> Vec<SaveList> {
-        self.exec_searcher(crate::searcher::IterSearcher::new(input.into_iter()))
+    /// Executes the program over the tokens of an iterator. Returns the matches found, according
+    /// to the program's `Semantics`, with the positions of all the save locations of each.
+    pub fn exec_iter<U: Borrow<T>>(&self, input: impl IntoIterator<Item = U>) -> Vec<Captures> {
+        self.exec(crate::searcher::IterSearcher::new(input.into_iter()))
     }
 
-    fn exec_searcher<U: Borrow<T>>(&self, mut searcher: impl Searcher<Item = U>) -> Vec<SaveList> {
-        // initialize thread lists. The number of threads should be limited by the length of the
+    /// Finds every way the program can match the input, regardless of the program's `Semantics`.
+    /// Returns the distinct lists of saved locations, grouped by the span of the overall match,
+    /// with the groups ordered by the start and then the end of the span, and the lists within
+    /// each group ordered by thread priority, as encoded by `Split` and `JSplit`.
+    ///
+    /// Ambiguous patterns can have very many parses, so if `limit` is given, the search stops
+    /// once that many have been found. Since matches are found in order of where they end, the
+    /// parses returned are then the ones with the earliest-ending spans. The limit only bounds
+    /// the output, not the running time: until it's reached, every path which hasn't matched yet
+    /// is still followed, and as with `AllPaths` semantics, their number can grow polynomially
+    /// with the length of the input. For example, `(a*)(a*)(a*)(a*)(a*)(a*)b` on a long run of
+    /// `a`s has no match to stop at, so it takes as long with a limit of 1 as without one.
+    pub fn all_parses<U: Borrow<T>>(
+        &self,
+        input: impl IntoSearcher<U>,
+        limit: Option<usize>,
+    ) -> Vec<Captures> {
+        let mut saves = self.exec_searcher(
+            &mut Cache::new(self),
+            input.into_searcher(),
+            0,
+            None,
+            Semantics::AllPaths,
+            limit,
+        );
+        if let Some(limit) = limit {
+            saves.truncate(limit);
+        }
+        // a stable sort, so that priority order is kept within each group
+        saves.sort_by_key(|saved| (saved.first().cloned(), saved.get(1).cloned()));
+        saves
+            .into_iter()
+            .map(|saved| self.captures_from(saved))
+            .collect()
+    }
+
+    /// Runs the program over the searcher's input with the given semantics, stopping once `limit`
+    /// matches have been found, if given. The searcher's first token is at index `idx`, and
+    /// follows the token `prev`, or the start of the input if `prev` is `None`.
+    pub(crate) fn exec_searcher<U: Borrow<T>>(
+        &self,
+        cache: &mut Cache,
+        mut searcher: impl Searcher<Item = U>,
+        mut idx: usize,
+        mut prev: Option<U>,
+        semantics: Semantics,
+        limit: Option<usize>,
+    ) -> Vec<SaveList> {
+        self.start_run(cache, semantics);
+        let mut saves = Vec::new();
+        // iterate over tokens of input string
+        loop {
+            let (next_idx, tok_i) = searcher.next();
+            let more = self.exec_step(
+                cache,
+                idx,
+                prev.as_ref().map(Borrow::borrow),
+                tok_i.as_ref().map(Borrow::borrow),
+                &mut saves,
+                limit,
+            );
+            if !more {
+                // return the list of saved locations
+                return saves;
+            }
+            // store the token and index for the next iteration
+            prev = tok_i;
+            idx = next_idx;
+        }
+    }
+
+    /// Starts a run of the program with the given semantics, using `cache` for its state. The run
+    /// is then driven by calling `exec_step` at each input position.
+    pub(crate) fn start_run(&self, cache: &mut Cache, semantics: Semantics) {
+        // initialize thread list. The number of threads should be limited by the length of the
         // program (since each instruction either ends a thread (in the case of a `Match` or a
         // failed `Token` instruction), continues an existing thread (in the case of a successful
         // `Token`, `Jump`, or `Save` instruction), or spawns a new thread (in the case of a
         // `Split` or `JSplit` instruction))
-        let mut curr = ThreadList::new(self.prog.len());
-        let mut next = ThreadList::new(self.prog.len());
-
-        let mut saves = Vec::new();
-
+        cache.reset(self, semantics);
         // start initial thread at start instruction
-        curr.add_thread(0, 0, self, vec![None; self.num_slots]);
+        cache.pending.push(0);
+        cache.pending_slots.push_unset();
+        cache.pending_histories.push(None);
+    }
 
-        // set initial word flag
-        let mut word = false;
+    /// Runs one step of a run started by `start_run`, at input index `idx`, between the tokens
+    /// `prev` and `next`, which are `None` at the start and the end of the input respectively.
+    /// Adds the threads left pending by the previous step, adds the saved locations of those
+    /// which have reached `Match` to `saves`, and advances the others past `next`. Returns
+    /// whether the run should go on to the next token, which it shouldn't at the end of the
+    /// input, once no threads are left, or once `limit` matches have been found.
+    pub(crate) fn exec_step(
+        &self,
+        cache: &mut Cache,
+        idx: usize,
+        prev: Option<&T>,
+        next: Option<&T>,
+        saves: &mut Vec<SaveList>,
+        limit: Option<usize>,
+    ) -> bool {
+        let Cache {
+            curr,
+            pending,
+            pending_slots,
+            pending_histories,
+            best,
+        } = cache;
+        let semantics = curr.semantics;
+        // now that the next token is known, add the pending threads
+        let ctx = Context { idx, prev, next };
+        curr.clear_visited();
+        // the threads added refer to the histories of the pending threads they were added from
+        mem::swap(&mut curr.histories, pending_histories);
+        for (i, pc) in pending.drain(..).enumerate() {
+            curr.add_thread(pc, &ctx, self, pending_slots.get(i), i);
+        }
+        pending_slots.clear();
+        if curr.len() == 0 {
+            // no threads are left, so there can't be any more matches. This only happens when the
+            // program is anchored, or when a match has already been found with leftmost
+            // semantics, since otherwise the thread which finds the start of the match is always
+            // running
+            return false;
+        }
+        let mut more = next.is_some();
+        // iterate over active threads, then clear the list so we can reuse it without
+        // reallocating. At the end of the input, only the threads which reached `Match` matter.
+        for i in 0..curr.len() {
+            let (pc, saved) = curr.get(i);
+            let (history, recent) = curr.history(i);
+            if let Instr::Match = self[pc] {
+                // add the saved locations to the final list
+                let path = Path::new(saved, history, recent, idx);
+                if add_match(semantics, saves, best, path, &self.repeated) {
+                    break;
+                }
+                if limit.is_some_and(|limit| saves.len() >= limit) {
+                    more = false;
+                    break;
+                }
+            } else if is_cut(semantics, saves, saved) {
+                continue;
+            } else if let Some(pc) = next.and_then(|tok| self.step(pc, tok)) {
+                // pass along saved positions
+                pending.push(pc);
+                pending_slots.push(saved);
+                if semantics == Semantics::LeftmostLongest {
+                    pending_histories.push(History::extend(history, recent, idx));
+                }
+            }
+        }
+        curr.clear();
+        // without any pending threads, the next step would find nothing
+        more && !pending.is_empty()
+    }
 
-        // to store the iteration number (declaring it here so it can be used in the final checks
-        // after the loop)
-        let mut i = 0;
+    /// Runs the program over the searcher's input, returning as soon as any thread reaches
+    /// `Match`. Since only whether there is a match matters, no saved locations are kept, and a
+    /// single thread per instruction is enough, whatever the program's `Semantics`.
+    pub(crate) fn exec_is_match<U: Borrow<T>>(
+        &self,
+        mut searcher: impl Searcher<Item = U>,
+    ) -> bool {
+        let mut curr = Vec::with_capacity(self.prog.len());
+        let mut pending = Vec::with_capacity(self.prog.len());
+        let mut visited = SparseSet::new(self.prog.len());
+        let mut stack = Vec::new();
 
-        // iterate over tokens of input string
-        while let (idx, Some(tok_i)) = searcher.next() {
-            let tok_i = tok_i.borrow();
-            // check if word boundary
-            let new_word = tok_i.is_word();
-            let word_boundary = new_word ^ word;
-            word = new_word;
-            // iterate over active threads, draining the list so we can reuse it without
-            // reallocating
-            for th in &mut curr {
-                use self::Instr::*;
-                match self[th.pc] {
-                    Token(ref token) => {
-                        // check if token matches
-                        if tok_i == token {
-                            // increment thread pc, passing along next input index, and saved
-                            // positions
-                            next.add_thread(th.pc + 1, idx, self, th.saved);
-                        }
-                    }
-                    Set(ref set) => {
-                        // check if token in set
-                        if set.contains(tok_i) {
-                            // increment thread pc, passing along next input index, and saved
-                            // positions
-                            next.add_thread(th.pc + 1, idx, self, th.saved);
-                        }
-                    }
-                    Map(ref map) => {
-                        // get the corresponding pc, or default to incrementing
-                        next.add_thread(
-                            map.get(tok_i).cloned().unwrap_or(th.pc + 1),
-                            idx,
-                            self,
-                            th.saved,
-                        );
-                    }
-                    Any => {
-                        // always matches
-                        next.add_thread(th.pc + 1, idx, self, th.saved);
-                    }
-                    WordBoundary => {
-                        // check if word boundary
-                        if word_boundary {
-                            next.add_thread(th.pc + 1, i, self, th.saved);
-                        }
-                    }
-                    Match => {
-                        // add the saved locations to the final list
-                        saves.push(th.saved);
-                    }
-                    // These instructions are handled in add_thread, so the current thread should
-                    // never point to one of them
-                    Split(_) | JSplit(_) | Jump(_) | Save(_) | Reject => {
-                        unreachable!();
-                    }
+        // if the program can only match at the start of the input, skip the `.*?` prefix, so that
+        // the thread list empties once no match is possible
+        pending.push(if self.is_start_anchored() { 3 } else { 0 });
+
+        let mut prev: Option<U> = None;
+        let mut idx = 0;
+        loop {
+            let (next_idx, tok_i) = searcher.next();
+            let ctx = Context {
+                idx,
+                prev: prev.as_ref().map(Borrow::borrow),
+                next: tok_i.as_ref().map(Borrow::borrow),
+            };
+            visited.clear();
+            for pc in pending.drain(..) {
+                if self.add_pc(pc, &ctx, &mut visited, &mut stack, &mut curr) {
+                    return true;
+                }
+            }
+            let tok_i = match tok_i {
+                Some(tok_i) => tok_i,
+                None => return false,
+            };
+            for pc in curr.drain(..) {
+                if let Some(pc) = self.step(pc, tok_i.borrow()) {
+                    pending.push(pc);
                 }
             }
-            // `next` becomes list of active threads, and `curr` (empty after iteration) can hold
-            // the next iteration
-            mem::swap(&mut curr, &mut next);
-            // increment`i`
-            i = idx;
-        }
-
-        // now iterate over remaining threads, to check for pending word boundary instructions
-        for th in &mut curr {
-            use self::Instr::*;
-            match self[th.pc] {
-                WordBoundary => {
-                    // check if last token was a word token
-                    if word {
-                        next.add_thread(th.pc + 1, i, self, th.saved);
+            if pending.is_empty() {
+                // no threads are left, so stop without reading any more of the input
+                return false;
+            }
+            prev = Some(tok_i);
+            idx = next_idx;
+        }
+    }
+
+    /// Adds a thread at `pc` to `threads` for `exec_is_match`, following `Jump`, `Split`,
+    /// `JSplit`, and `Save` instructions and zero-width assertions like `ThreadList::add_thread`,
+    /// but without keeping track of saved locations. Returns `true` as soon as a thread reaches
+    /// `Match`. `stack` is used for the search, and is left empty.
+    fn add_pc(
+        &self,
+        pc: InstrPtr,
+        ctx: &Context<T>,
+        visited: &mut SparseSet,
+        stack: &mut Vec<InstrPtr>,
+        threads: &mut Vec<InstrPtr>,
+    ) -> bool {
+        use self::Instr::*;
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if !visited.insert(pc) {
+                continue;
+            }
+            match self[pc] {
+                Split(split) | JSplit(split) => {
+                    stack.push(split);
+                    stack.push(pc + 1);
+                }
+                Jump(jump) => stack.push(jump),
+                Save(_) => stack.push(pc + 1),
+                StartOfInput | EndOfInput | StartOfLine | EndOfLine | WordBoundary
+                | NotWordBoundary => {
+                    if ctx.assert(&self[pc]) {
+                        stack.push(pc + 1);
                     }
                 }
+                Reject => {}
                 Match => {
-                    saves.push(th.saved);
+                    stack.clear();
+                    return true;
                 }
-                // anything else is a failed match
-                _ => {}
+                Token(_) | Map(_) | Set(_) | NotSet(_) | Ranges(_) | Class(_) | Fold(_) | Any
+                | NotLineTerminator => threads.push(pc),
             }
         }
+        false
+    }
 
-        // now iterate over remaining threads, to check for pending match instructions
-        for th in &mut next {
-            // anything else is a failed match
-            if let Instr::Match = self[th.pc] {
-                saves.push(th.saved);
+    /// Whether the program starts with the `.*?` prefix of an unanchored program, but every path
+    /// after the prefix passes a `StartOfInput` assertion before consuming a token, so that the
+    /// prefix can't lead to a match once it has consumed a token.
+    fn is_start_anchored(&self) -> bool {
+        use self::Instr::*;
+        if self.prog.get(..3) != Some(&[JSplit(3), Any, Jump(0)][..]) {
+            return false;
+        }
+        let mut visited = SparseSet::new(self.prog.len());
+        let mut stack = vec![3];
+        while let Some(pc) = stack.pop() {
+            if !visited.insert(pc) {
+                continue;
+            }
+            match self[pc] {
+                StartOfInput | Reject => {}
+                Split(split) | JSplit(split) => {
+                    stack.push(pc + 1);
+                    stack.push(split);
+                }
+                Jump(jump) => stack.push(jump),
+                Save(_) | EndOfInput | StartOfLine | EndOfLine | WordBoundary | NotWordBoundary => {
+                    stack.push(pc + 1)
+                }
+                Token(_) | Map(_) | Set(_) | NotSet(_) | Ranges(_) | Class(_) | Fold(_) | Any
+                | NotLineTerminator | Match => return false,
             }
         }
+        true
+    }
 
-        // return the list of saved locations
-        saves
+    /// Executes the instruction at `pc` against the input token `tok`. If the instruction
+    /// matches, returns the `InstrPtr` the thread should continue at, otherwise returns `None`.
+    /// The instruction must be one which consumes a token.
+    fn step(&self, pc: InstrPtr, tok: &T) -> Option<InstrPtr> {
+        self[pc].step(pc, tok)
+    }
+}
+
+impl<T: Token> Instr<T> {
+    /// Executes this instruction, located at `pc`, against the input token `tok`. See
+    /// `Program::step`.
+    fn step(&self, pc: InstrPtr, tok: &T) -> Option<InstrPtr> {
+        use self::Instr::*;
+        let matches = match *self {
+            // check if token matches
+            Token(ref token) => tok == token,
+            // check if token in set
+            Set(ref set) => set.contains(tok),
+            // check if token not in set
+            NotSet(ref set) => !set.contains(tok),
+            // check if token in ranges
+            Ranges(ref ranges) => ranges.contains(tok),
+            // check if token in class
+            Class(ref class) => class.contains(tok),
+            // get the corresponding pc, or default to incrementing
+            Map(ref map) => return Some(map.get(tok).cloned().unwrap_or(pc + 1)),
+            Fold(ref instr) => match **instr {
+                // ranges aren't folded, so check the other forms of the token against them too
+                Ranges(ref ranges) => ranges.contains_any(&[tok, &tok.fold(), &tok.fold_variant()]),
+                // run the inner instruction against the folded token
+                ref instr => return instr.step(pc, &tok.fold()),
+            },
+            // always matches
+            Any => true,
+            // check if token isn't a line terminator
+            NotLineTerminator => !tok.is_line_terminator(),
+            // These instructions are handled in add_thread or by the caller, so the current
+            // thread should never point to one of them
+            Split(_) | JSplit(_) | Jump(_) | Save(_) | StartOfInput | EndOfInput | StartOfLine
+            | EndOfLine | WordBoundary | NotWordBoundary | Reject | Match => unreachable!(),
+        };
+        // increment thread pc
+        if matches {
+            Some(pc + 1)
+        } else {
+            None
+        }
     }
 }
 
@@ -7,33 +7,53 @@ macro_rules! program {
             count += 1;
         )*
         let mut prog = Vec::with_capacity(count);
+        // only mutated if the program contains a `Save` instruction
+        #[allow(unused_mut)]
         let mut max_slot = 0;
+        // only mutated if the program contains a named `Save` instruction
+        #[allow(unused_mut)]
+        let mut names = ::std::collections::HashMap::new();
         $(
-            let instr = $crate::instruction!($instr $(($($args)*))?, max_slot);
+            let instr = $crate::instruction!($instr $(($($args)*))?, max_slot, names);
             prog.push(instr);
         )*
-        $crate::program::Program::new(prog, max_slot + 1)
+        $crate::program::Program::new(prog, max_slot + 1).with_names(names)
     }};
 }
 
 #[macro_export]
 macro_rules! instruction {
-    (Map($($tok:expr => $label:expr),*), $max_slot:ident) => {
-        $crate::program::Instr::Map([$(($tok, $label)),*].into_iter().collect())
+    (Map($($tok:expr => $label:expr),*), $max_slot:ident, $names:ident) => {
+        $crate::program::Instr::Map(::std::iter::IntoIterator::into_iter([$(($tok, $label)),*]).collect())
     };
-    (Set($($tok:expr),*), $max_slot:ident) => {
-        $crate::program::Instr::Set([$($tok),*].into_iter().collect())
+    (Set($($tok:expr),*), $max_slot:ident, $names:ident) => {
+        $crate::program::Instr::Set(::std::iter::IntoIterator::into_iter([$($tok),*]).collect())
     };
-    (Save($slot:expr), $max_slot:ident) => {{
+    (NotSet($($tok:expr),*), $max_slot:ident, $names:ident) => {
+        $crate::program::Instr::NotSet(::std::iter::IntoIterator::into_iter([$($tok),*]).collect())
+    };
+    (Fold($($instr:tt)*), $max_slot:ident, $names:ident) => {
+        $crate::program::Instr::Fold(::std::boxed::Box::new($crate::instruction!(
+            $($instr)*,
+            $max_slot,
+            $names
+        )))
+    };
+    (Save($slot:expr), $max_slot:ident, $names:ident) => {{
         $max_slot = $max_slot.max($slot);
         $crate::program::Instr::Save($slot)
     }};
+    // a `Save` which names the group of its slot, i.e. group `slot / 2`
+    (Save($slot:expr, $name:expr), $max_slot:ident, $names:ident) => {{
+        $names.insert(::std::string::String::from($name), $slot / 2);
+        $crate::instruction!(Save($slot), $max_slot, $names)
+    }};
     // Any, WordBoundary, Reject, Match
-    ($instr:tt, $max_slot:ident) => {
+    ($instr:tt, $max_slot:ident, $names:ident) => {
         $crate::program::Instr::$instr
     };
-    // Token, Split, JSplit, Jump
-    ($instr:tt ($arg:expr), $max_slot:ident) => {
+    // Token, Ranges, Class, Split, JSplit, Jump
+    ($instr:tt ($arg:expr), $max_slot:ident, $names:ident) => {
         $crate::program::Instr::$instr($arg)
     };
 }
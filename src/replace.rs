@@ -0,0 +1,261 @@
+use std::borrow::Cow;
+
+use crate::captures::Captures;
+use crate::program::Program;
+use crate::token::Token;
+
+/// Produces the replacement for each match in a `&str` haystack. Implemented for template strings,
+/// which are expanded by `Captures::expand`, and for closures which compute the replacement from
+/// the `Captures` of the match.
+pub trait Replacer {
+    /// Appends the replacement for the match `caps` in `haystack` to `dst`.
+    fn replace_append(&mut self, caps: &Captures, haystack: &str, dst: &mut String);
+}
+
+impl Replacer for &str {
+    fn replace_append(&mut self, caps: &Captures, haystack: &str, dst: &mut String) {
+        caps.expand(haystack, self, dst);
+    }
+}
+
+impl<F, S> Replacer for F
+where
+    F: FnMut(&Captures) -> S,
+    S: AsRef<str>,
+{
+    fn replace_append(&mut self, caps: &Captures, _: &str, dst: &mut String) {
+        dst.push_str(self(caps).as_ref());
+    }
+}
+
+/// Produces the replacement for each match in a slice of tokens. Implemented for slices, which are
+/// inserted as they are, and for closures which compute the replacement tokens from the `Captures`
+/// of the match.
+pub trait TokenReplacer<T> {
+    /// Appends the replacement for the match `caps` in `haystack` to `dst`.
+    fn replace_append(&mut self, caps: &Captures, haystack: &[T], dst: &mut Vec<T>);
+}
+
+impl<T: Clone> TokenReplacer<T> for &[T] {
+    fn replace_append(&mut self, _: &Captures, _: &[T], dst: &mut Vec<T>) {
+        dst.extend_from_slice(self);
+    }
+}
+
+impl<T, F> TokenReplacer<T> for F
+where
+    F: FnMut(&Captures) -> Vec<T>,
+{
+    fn replace_append(&mut self, caps: &Captures, _: &[T], dst: &mut Vec<T>) {
+        dst.append(&mut self(caps));
+    }
+}
+
+impl Captures {
+    /// Expands `template` for this match in `haystack`, appending the result to `dst`.
+    ///
+    /// `$n` and `${n}` are replaced by the text of group _n_, and `$name` and `${name}` by the text
+    /// of the group with that name. An unbraced reference takes the longest run of letters,
+    /// digits, and underscores after the `$`, so `${1}a` is needed to follow group 1 with an `a`.
+    /// References to groups which don't exist or didn't participate in the match are replaced by
+    /// nothing. `$$` is replaced by a literal `$`, as is any `$` which doesn't start a reference.
+    pub fn expand(&self, haystack: &str, template: &str, dst: &mut String) {
+        let mut rest = template;
+        while let Some(i) = rest.find('$') {
+            dst.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                dst.push('$');
+                rest = after;
+                continue;
+            }
+            let (name, after) = match parse_ref(rest) {
+                Some(parsed) => parsed,
+                None => {
+                    dst.push('$');
+                    continue;
+                }
+            };
+            let group = match name.parse::<usize>() {
+                Ok(group) => self.slice(haystack, group),
+                Err(_) => self.name(name).map(|range| &haystack[range]),
+            };
+            dst.push_str(group.unwrap_or(""));
+            rest = after;
+        }
+        dst.push_str(rest);
+    }
+}
+
+/// Parses the group reference at the start of `template`, just after a `$`. Returns the name or
+/// number of the group, and the rest of the template.
+fn parse_ref(template: &str) -> Option<(&str, &str)> {
+    if let Some(braced) = template.strip_prefix('{') {
+        let end = braced.find('}')?;
+        if end == 0 {
+            return None;
+        }
+        return Some((&braced[..end], &braced[end + 1..]));
+    }
+    let end = template
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(template.len());
+    if end == 0 {
+        None
+    } else {
+        Some(template.split_at(end))
+    }
+}
+
+impl Program<char> {
+    /// Replaces the leftmost match in `haystack`, as found by `find`, with the replacement given
+    /// by `rep`. Returns the haystack unchanged, without copying it, if there is no match.
+    pub fn replace<'h>(&self, haystack: &'h str, rep: impl Replacer) -> Cow<'h, str> {
+        self.replacen(haystack, 1, rep)
+    }
+
+    /// Replaces every non-overlapping match in `haystack`, as found by `find_iter`, with the
+    /// replacement given by `rep`.
+    pub fn replace_all<'h>(&self, haystack: &'h str, rep: impl Replacer) -> Cow<'h, str> {
+        self.replacen(haystack, 0, rep)
+    }
+
+    /// Replaces the first `limit` non-overlapping matches in `haystack`, or every match if `limit`
+    /// is 0, with the replacement given by `rep`.
+    pub fn replacen<'h>(
+        &self,
+        haystack: &'h str,
+        limit: usize,
+        mut rep: impl Replacer,
+    ) -> Cow<'h, str> {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let mut dst = String::new();
+        let mut last = 0;
+        let mut replaced = false;
+        for caps in self.captures_iter(haystack).take(limit) {
+            let m = match caps.get_match() {
+                Some(m) => m,
+                None => break,
+            };
+            dst.push_str(&haystack[last..m.start()]);
+            rep.replace_append(&caps, haystack, &mut dst);
+            last = m.end();
+            replaced = true;
+        }
+        if !replaced {
+            return Cow::Borrowed(haystack);
+        }
+        dst.push_str(&haystack[last..]);
+        Cow::Owned(dst)
+    }
+}
+
+impl<T: Token> Program<T> {
+    /// Replaces the leftmost match in a slice of tokens with the replacement given by `rep`. See
+    /// `replace`.
+    pub fn replace_tokens<'h>(
+        &self,
+        haystack: &'h [T],
+        rep: impl TokenReplacer<T>,
+    ) -> Cow<'h, [T]> {
+        self.replacen_tokens(haystack, 1, rep)
+    }
+
+    /// Replaces every non-overlapping match in a slice of tokens with the replacement given by
+    /// `rep`. See `replace_all`.
+    pub fn replace_all_tokens<'h>(
+        &self,
+        haystack: &'h [T],
+        rep: impl TokenReplacer<T>,
+    ) -> Cow<'h, [T]> {
+        self.replacen_tokens(haystack, 0, rep)
+    }
+
+    /// Replaces the first `limit` non-overlapping matches in a slice of tokens, or every match if
+    /// `limit` is 0, with the replacement given by `rep`. See `replacen`.
+    pub fn replacen_tokens<'h>(
+        &self,
+        haystack: &'h [T],
+        limit: usize,
+        mut rep: impl TokenReplacer<T>,
+    ) -> Cow<'h, [T]> {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let mut dst = Vec::new();
+        let mut last = 0;
+        let mut replaced = false;
+        for caps in self.captures_iter(haystack).take(limit) {
+            let m = match caps.get_match() {
+                Some(m) => m,
+                None => break,
+            };
+            dst.extend_from_slice(&haystack[last..m.start()]);
+            rep.replace_append(&caps, haystack, &mut dst);
+            last = m.end();
+            replaced = true;
+        }
+        if !replaced {
+            return Cow::Borrowed(haystack);
+        }
+        dst.extend_from_slice(&haystack[last..]);
+        Cow::Owned(dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use crate::captures::Captures;
+    use crate::tests::compile;
+
+    #[test]
+    fn replace() {
+        let prog = compile("([a-z]+)=([0-9]*)");
+        let haystack = "a=1, bc=, é d=23";
+        assert_eq!(prog.replace(haystack, "$2:$1"), "1:a, bc=, é d=23");
+        assert_eq!(prog.replace_all(haystack, "$2:$1"), "1:a, :bc, é 23:d");
+        assert_eq!(prog.replacen(haystack, 2, "<$0>"), "<a=1>, <bc=>, é d=23");
+        // braces, escapes, and references to missing groups
+        assert_eq!(
+            prog.replace_all(haystack, "${1}x$$$3$"),
+            "ax$$, bcx$$, é dx$$"
+        );
+        assert_eq!(prog.replace_all(haystack, "$1x"), ", , é ");
+        assert_eq!(prog.replace(haystack, "${}"), "${}, bc=, é d=23");
+        // no copy is made when nothing matches
+        assert!(matches!(
+            prog.replace_all("no match", "x"),
+            Cow::Borrowed(_)
+        ));
+        // closures get the captures of each match
+        let doubled = prog.replace_all(haystack, |caps: &Captures| {
+            let n = caps.slice(haystack, 2).unwrap().parse::<u32>().unwrap_or(0);
+            (n * 2).to_string()
+        });
+        assert_eq!(doubled, "2, 0, é 46");
+        // empty matches
+        assert_eq!(compile("a*").replace_all("baac", "-"), "-b-c-");
+    }
+
+    #[test]
+    fn replace_tokens() {
+        let prog = compile("a+");
+        let haystack = "baac".chars().collect::<Vec<_>>();
+        assert_eq!(
+            prog.replace_all_tokens(&haystack, &['x', 'y'][..]),
+            vec!['b', 'x', 'y', 'c']
+        );
+        let prog = compile("([ab])([cd])");
+        let haystack = "acxbdbc".chars().collect::<Vec<_>>();
+        let swapped = prog.replacen_tokens(&haystack, 2, |caps: &Captures| {
+            let mut toks = caps.slice(&haystack[..], 2).unwrap().to_vec();
+            toks.extend_from_slice(caps.slice(&haystack[..], 1).unwrap());
+            toks
+        });
+        assert_eq!(swapped.iter().collect::<String>(), "caxdbbc");
+        assert!(matches!(
+            prog.replace_tokens(&['x'], &[][..]),
+            Cow::Borrowed(_)
+        ));
+    }
+}
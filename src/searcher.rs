@@ -1,9 +1,19 @@
+use std::collections::VecDeque;
+
 pub trait Searcher {
     type Item;
 
     fn next(&mut self) -> (usize, Option<Self::Item>);
 }
 
+impl<S: Searcher + ?Sized> Searcher for &mut S {
+    type Item = S::Item;
+
+    fn next(&mut self) -> (usize, Option<S::Item>) {
+        (**self).next()
+    }
+}
+
 pub struct StrSearcher<'a> {
     next_index: usize,
     iter: std::str::Chars<'a>,
@@ -47,6 +57,112 @@ impl<I: Iterator> Searcher for IterSearcher<I> {
     }
 }
 
+/// A searcher which keeps the tokens it reads from another searcher, so that a search can be
+/// restarted from a later position without having to read the input again. Used to run successive
+/// searches over an input which can only be read once.
+pub(crate) struct Replay<S: Searcher> {
+    searcher: S,
+    /// The tokens which have been read from `searcher`, starting at the start position, each with
+    /// the index following it.
+    buf: VecDeque<(usize, S::Item)>,
+    /// The number of tokens in `buf` which have been read since the last restart.
+    pos: usize,
+    /// The index of the start position.
+    start: usize,
+    /// The token before the start position, or `None` at the start of the input.
+    prev: Option<S::Item>,
+    /// The index of the end of the input, once `searcher` has been exhausted.
+    end: Option<usize>,
+}
+
+impl<S: Searcher> Replay<S>
+where
+    S::Item: Clone,
+{
+    pub(crate) fn new(searcher: S) -> Replay<S> {
+        Replay {
+            searcher,
+            buf: VecDeque::new(),
+            pos: 0,
+            start: 0,
+            prev: None,
+            end: None,
+        }
+    }
+
+    /// The index of the start position.
+    pub(crate) fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The token before the start position, or `None` at the start of the input.
+    pub(crate) fn prev(&self) -> Option<S::Item> {
+        self.prev.clone()
+    }
+
+    /// Moves the start position forward to `idx`, which must be the index following a token which
+    /// has already been read, and rewinds to it.
+    pub(crate) fn restart_at(&mut self, idx: usize) {
+        self.drop_before(idx);
+        self.start = idx;
+        self.pos = 0;
+    }
+
+    /// Drops the tokens before index `idx`, which must not be after the current position, moving
+    /// the start position forward to it if it's later, without rewinding.
+    pub(crate) fn drop_before(&mut self, idx: usize) {
+        while let Some(&(next_idx, _)) = self.buf.front() {
+            if next_idx > idx {
+                break;
+            }
+            self.prev = self.buf.pop_front().map(|(_, tok)| tok);
+            self.pos = self.pos.saturating_sub(1);
+            self.start = next_idx;
+        }
+    }
+
+    /// Moves the start position forward by one token, and rewinds to it. Returns `false` if the
+    /// start position is already at the end of the input.
+    pub(crate) fn restart_after_next(&mut self) -> bool {
+        self.pos = 0;
+        match self.next() {
+            (idx, Some(_)) => {
+                self.restart_at(idx);
+                true
+            }
+            (_, None) => false,
+        }
+    }
+}
+
+impl<S: Searcher> Searcher for Replay<S>
+where
+    S::Item: Clone,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> (usize, Option<S::Item>) {
+        if let Some((idx, tok)) = self.buf.get(self.pos) {
+            self.pos += 1;
+            return (*idx, Some(tok.clone()));
+        }
+        if let Some(end) = self.end {
+            return (end, None);
+        }
+        match self.searcher.next() {
+            (idx, Some(tok)) => {
+                self.buf.push_back((idx, tok.clone()));
+                self.pos += 1;
+                (idx, Some(tok))
+            }
+            (idx, None) => {
+                self.end = Some(idx);
+                (idx, None)
+            }
+        }
+    }
+}
+
 pub trait IntoSearcher<T> {
     type Searcher: Searcher<Item = T>;
 
@@ -0,0 +1,169 @@
+use std::borrow::Borrow;
+use std::ops::{Index, Range, RangeFrom};
+
+use crate::find::FindIter;
+use crate::program::Program;
+use crate::searcher::{IntoSearcher, Searcher};
+use crate::token::Token;
+
+impl<T: Token> Program<T> {
+    /// Returns an iterator over the pieces of `haystack` between the successive non-overlapping
+    /// matches of the program, as found by `find_iter`. The haystack can be a `&str`, which is
+    /// split into `&str`s at byte offsets, or a `&[T]`, which is split into subslices at token
+    /// positions. A match at the start or end of the haystack produces an empty piece before or
+    /// after it, and adjacent matches produce an empty piece between them.
+    pub fn split<'h, H, U>(
+        &self,
+        haystack: &'h H,
+    ) -> Split<'_, 'h, T, H, <&'h H as IntoSearcher<U>>::Searcher>
+    where
+        H: Index<Range<usize>, Output = H> + Index<RangeFrom<usize>, Output = H> + ?Sized,
+        &'h H: IntoSearcher<U>,
+        U: Borrow<T> + Clone,
+    {
+        Split {
+            haystack,
+            matches: self.find_iter(haystack),
+            last: 0,
+            done: false,
+        }
+    }
+
+    /// Returns an iterator over at most `limit` pieces of `haystack`, split as by `split`. The
+    /// last piece contains the rest of the haystack, including any further matches.
+    pub fn splitn<'h, H, U>(
+        &self,
+        haystack: &'h H,
+        limit: usize,
+    ) -> SplitN<'_, 'h, T, H, <&'h H as IntoSearcher<U>>::Searcher>
+    where
+        H: Index<Range<usize>, Output = H> + Index<RangeFrom<usize>, Output = H> + ?Sized,
+        &'h H: IntoSearcher<U>,
+        U: Borrow<T> + Clone,
+    {
+        SplitN {
+            split: self.split(haystack),
+            limit,
+        }
+    }
+}
+
+/// An iterator over the pieces of a haystack between the matches of a program. Created by
+/// `Program::split`.
+pub struct Split<'p, 'h, T: Token, H: ?Sized, S: Searcher> {
+    haystack: &'h H,
+    matches: FindIter<'p, T, S>,
+    /// The end of the previous match, i.e. the start of the next piece.
+    last: usize,
+    /// Whether the last piece has been returned.
+    done: bool,
+}
+
+impl<'p, 'h, T, H, S, U> Split<'p, 'h, T, H, S>
+where
+    T: Token,
+    H: Index<Range<usize>, Output = H> + Index<RangeFrom<usize>, Output = H> + ?Sized,
+    S: Searcher<Item = U>,
+    U: Borrow<T> + Clone,
+{
+    /// Returns the rest of the haystack, if the last piece hasn't been returned yet.
+    fn finish(&mut self) -> Option<&'h H> {
+        if self.done {
+            None
+        } else {
+            self.done = true;
+            Some(&self.haystack[self.last..])
+        }
+    }
+}
+
+impl<'p, 'h, T, H, S, U> Iterator for Split<'p, 'h, T, H, S>
+where
+    T: Token,
+    H: Index<Range<usize>, Output = H> + Index<RangeFrom<usize>, Output = H> + ?Sized,
+    S: Searcher<Item = U>,
+    U: Borrow<T> + Clone,
+{
+    type Item = &'h H;
+
+    fn next(&mut self) -> Option<&'h H> {
+        if self.done {
+            return None;
+        }
+        match self.matches.next() {
+            Some(m) => {
+                let piece = &self.haystack[self.last..m.start()];
+                self.last = m.end();
+                Some(piece)
+            }
+            None => self.finish(),
+        }
+    }
+}
+
+/// An iterator over at most a given number of pieces of a haystack between the matches of a
+/// program. Created by `Program::splitn`.
+pub struct SplitN<'p, 'h, T: Token, H: ?Sized, S: Searcher> {
+    split: Split<'p, 'h, T, H, S>,
+    /// The number of pieces which can still be returned.
+    limit: usize,
+}
+
+impl<'p, 'h, T, H, S, U> Iterator for SplitN<'p, 'h, T, H, S>
+where
+    T: Token,
+    H: Index<Range<usize>, Output = H> + Index<RangeFrom<usize>, Output = H> + ?Sized,
+    S: Searcher<Item = U>,
+    U: Borrow<T> + Clone,
+{
+    type Item = &'h H;
+
+    fn next(&mut self) -> Option<&'h H> {
+        match self.limit {
+            0 => None,
+            1 => {
+                self.limit = 0;
+                self.split.finish()
+            }
+            _ => {
+                self.limit -= 1;
+                self.split.next()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::compile;
+
+    #[test]
+    fn split() {
+        let prog = compile(" *, *");
+        let pieces = prog.split("a, b ,c,, é").collect::<Vec<_>>();
+        assert_eq!(pieces, vec!["a", "b", "c", "", "é"]);
+        assert_eq!(prog.split("").collect::<Vec<_>>(), vec![""]);
+        assert_eq!(prog.split(",a,").collect::<Vec<_>>(), vec!["", "a", ""]);
+        assert_eq!(
+            compile("a*").split("baac").collect::<Vec<_>>(),
+            vec!["", "b", "c", ""]
+        );
+
+        let pieces = prog.splitn("a, b ,c", 2).collect::<Vec<_>>();
+        assert_eq!(pieces, vec!["a", "b ,c"]);
+        assert_eq!(prog.splitn("a,b", 5).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(prog.splitn("a,b", 1).collect::<Vec<_>>(), vec!["a,b"]);
+        assert_eq!(prog.splitn("a,b", 0).count(), 0);
+    }
+
+    #[test]
+    fn split_tokens() {
+        // a transcript split on runs of pause markers
+        let prog = compile("_+");
+        let transcript = "ab_c__d".chars().collect::<Vec<_>>();
+        let pieces = prog.split(&transcript[..]).collect::<Vec<_>>();
+        assert_eq!(pieces, vec![&['a', 'b'][..], &['c'], &['d']]);
+        let pieces = prog.splitn(&transcript[..], 2).collect::<Vec<_>>();
+        assert_eq!(pieces, vec![&['a', 'b'][..], &['c', '_', '_', 'd']]);
+    }
+}
@@ -3,9 +3,31 @@ use std::fmt;
 use std::hash;
 
 /// A single token for matching
-pub trait Token: cmp::Eq + fmt::Debug + hash::Hash {
+pub trait Token: Clone + cmp::Eq + fmt::Debug + hash::Hash {
     /// Returns whether the `Token` should be considered a word character.
     fn is_word(&self) -> bool;
+
+    /// Returns whether the `Token` ends a line, e.g. a newline character, or a sentence separator
+    /// in a stream of words. Used by the `StartOfLine` and `EndOfLine` assertions. Defaults to
+    /// `false`, so that the whole input is a single line.
+    fn is_line_terminator(&self) -> bool {
+        false
+    }
+
+    /// Returns the case folded form of the `Token`, which is the same for any two tokens which
+    /// should match each other in case-insensitive mode. Defaults to the token itself, so that
+    /// case-insensitive mode has no effect.
+    fn fold(&self) -> Self {
+        self.clone()
+    }
+
+    /// Returns another form of the `Token` with the same case folding, e.g. its uppercase form.
+    /// Ranges hold tokens in their usual forms rather than folded ones, so in case-insensitive
+    /// mode a token is tested against a range as it is, after `fold`, and in this form, which lets
+    /// both `[a-z]` and `[A-Z]` match any letter. Defaults to the token itself.
+    fn fold_variant(&self) -> Self {
+        self.clone()
+    }
 }
 
 impl Token for char {
@@ -13,4 +35,31 @@ impl Token for char {
     fn is_word(&self) -> bool {
         !self.is_whitespace()
     }
+
+    /// Returns `true` if the character is `'\n'`.
+    fn is_line_terminator(&self) -> bool {
+        *self == '\n'
+    }
+
+    /// Returns the lowercase form of the uppercase form of the character, which approximates
+    /// Unicode simple case folding, e.g. `'A'`, `'a'` → `'a'`, and `'Σ'`, `'σ'`, `'ς'` → `'σ'`.
+    /// Characters whose case mappings are more than one character, like `'ß'` → `"SS"`, skip that
+    /// step.
+    fn fold(&self) -> char {
+        let upper = single(self.to_uppercase()).unwrap_or(*self);
+        single(upper.to_lowercase()).unwrap_or(upper)
+    }
+
+    /// Returns the uppercase form of the character, unless it is more than one character.
+    fn fold_variant(&self) -> char {
+        single(self.to_uppercase()).unwrap_or(*self)
+    }
+}
+
+/// Returns the only character of `chars`, or `None` if there are zero or several.
+fn single(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
 }